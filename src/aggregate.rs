@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+
+use {DataPoint, Decode};
+use decode;
+
+/// DEFAULT_RELATIVE_ACCURACY is the default relative accuracy of the quantiles estimated by a
+/// `Sketch`, one percent
+pub const DEFAULT_RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Sketch
+///
+/// Sketch estimates quantiles of a set of values without holding on to them. Values are counted in
+/// buckets whose bounds grow exponentially, so the number of buckets only grows with the logarithm
+/// of the range of values and every quantile is estimated to within the relative accuracy the
+/// sketch was created with. Sketches with the same relative accuracy can be merged without losing
+/// any accuracy, so a sketch per block can be combined into one for a whole range.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Sketch {
+    gamma: f64, // ratio between the bounds of consecutive buckets
+    positive: BTreeMap<i32, u64>, // counts of positive values by bucket
+    negative: BTreeMap<i32, u64>, // counts of negative values by the bucket of their magnitude
+    zero: u64, // count of zeros
+    count: u64,
+}
+
+impl Sketch {
+    /// new creates a new Sketch which estimates quantiles to within `relative_accuracy`, which must
+    /// be between zero and one
+    pub fn new(relative_accuracy: f64) -> Self {
+        assert!(relative_accuracy > 0.0 && relative_accuracy < 1.0,
+                "relative accuracy must be between zero and one");
+
+        Sketch {
+            gamma: (1.0 + relative_accuracy) / (1.0 - relative_accuracy),
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zero: 0,
+            count: 0,
+        }
+    }
+
+    /// add adds `value` to the sketch
+    pub fn add(&mut self, value: i64) {
+        self.count += 1;
+        if value == 0 {
+            self.zero += 1;
+            return;
+        }
+
+        let i = self.index(value.unsigned_abs() as f64);
+        let buckets = if value > 0 {
+            &mut self.positive
+        } else {
+            &mut self.negative
+        };
+        *buckets.entry(i).or_default() += 1;
+    }
+
+    /// merge adds every value counted by `other`, which must have the same relative accuracy, to
+    /// the sketch
+    pub fn merge(&mut self, other: &Sketch) {
+        assert!(self.gamma == other.gamma,
+                "sketches must have the same relative accuracy to be merged");
+
+        for (&i, &n) in &other.positive {
+            *self.positive.entry(i).or_default() += n;
+        }
+        for (&i, &n) in &other.negative {
+            *self.negative.entry(i).or_default() += n;
+        }
+        self.zero += other.zero;
+        self.count += other.count;
+    }
+
+    /// len returns the number of values added to the sketch
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// is_empty returns true if no values have been added to the sketch
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// quantile estimates the `q` quantile, between zero and one, of the values added to the
+    /// sketch, returning None if it is empty
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        assert!((0.0..=1.0).contains(&q), "quantile must be between zero and one");
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = (q * (self.count - 1) as f64).round() as u64;
+
+        // negative values are smallest for the largest magnitudes, so walk their buckets backwards
+        let mut seen = 0;
+        for (&i, &n) in self.negative.iter().rev() {
+            seen += n;
+            if seen > rank {
+                return Some(-self.value(i));
+            }
+        }
+
+        seen += self.zero;
+        if seen > rank {
+            return Some(0.0);
+        }
+
+        for (&i, &n) in &self.positive {
+            seen += n;
+            if seen > rank {
+                return Some(self.value(i));
+            }
+        }
+
+        unreachable!("rank is always less than the number of values")
+    }
+
+    // index returns the bucket holding values of magnitude, which covers (gamma^(i-1), gamma^i]
+    fn index(&self, magnitude: f64) -> i32 {
+        (magnitude.ln() / self.gamma.ln()).ceil() as i32
+    }
+
+    // value returns the estimate for values in bucket i, which is within the relative accuracy of
+    // every value in the bucket
+    fn value(&self, i: i32) -> f64 {
+        2.0 * self.gamma.powi(i) / (self.gamma + 1.0)
+    }
+}
+
+impl Default for Sketch {
+    fn default() -> Self {
+        Sketch::new(DEFAULT_RELATIVE_ACCURACY)
+    }
+}
+
+/// Summary
+///
+/// Summary aggregates the values of `DataPoint`s as they are decoded, without holding on to the
+/// `DataPoint`s themselves. The sum, min, max and count are exact while quantiles are estimated by
+/// a `Sketch`. Summaries can be merged, so a summary of each block or series can be combined into a
+/// summary of all of them.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Summary {
+    count: u64,
+    sum: i128,
+    min: Option<i64>,
+    max: Option<i64>,
+    mean: f64, // running mean used to calculate m2
+    m2: f64, // sum of squared differences from the mean
+    sketch: Sketch,
+}
+
+impl Summary {
+    /// new creates a new, empty, Summary whose quantiles have DEFAULT_RELATIVE_ACCURACY
+    pub fn new() -> Self {
+        Summary::default()
+    }
+
+    /// with_relative_accuracy creates a new, empty, Summary whose quantiles are estimated to within
+    /// `relative_accuracy`
+    pub fn with_relative_accuracy(relative_accuracy: f64) -> Self {
+        Summary { sketch: Sketch::new(relative_accuracy), ..Summary::default() }
+    }
+
+    /// add adds the value of `dp` to the summary
+    pub fn add(&mut self, dp: DataPoint) {
+        let value = dp.value;
+
+        self.count += 1;
+        self.sum += value as i128;
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+
+        // Welford's algorithm keeps the variance accurate for large values
+        let delta = value as f64 - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value as f64 - self.mean);
+
+        self.sketch.add(value);
+    }
+
+    /// consume adds every `DataPoint` read from `decoder` until the end of its stream
+    pub fn consume<D: Decode>(&mut self, decoder: &mut D) -> Result<(), decode::Error> {
+        self.consume_range(decoder, 0, u64::MAX)
+    }
+
+    /// consume_range adds the `DataPoint`s read from `decoder` whose timestamps are between
+    /// `start`, inclusive, and `end`, exclusive. Decoding stops at the first `DataPoint` at or
+    /// after `end`
+    pub fn consume_range<D: Decode>(&mut self,
+                                    decoder: &mut D,
+                                    start: u64,
+                                    end: u64)
+                                    -> Result<(), decode::Error> {
+        decode::for_each(decoder, |dp| {
+            if dp.time >= start && dp.time < end {
+                self.add(dp);
+            }
+            dp.time < end
+        })
+    }
+
+    /// merge combines `other` into the summary
+    pub fn merge(&mut self, other: &Summary) {
+        if other.count == 0 {
+            return;
+        }
+
+        // Chan et al's method of combining the variance of two sets
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * self.count as f64 * other.count as f64 / count as f64;
+
+        self.count = count;
+        self.sum += other.sum;
+        self.min = self.min.into_iter().chain(other.min).min();
+        self.max = self.max.into_iter().chain(other.max).max();
+        self.sketch.merge(&other.sketch);
+    }
+
+    /// count returns the number of `DataPoint`s added
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// sum returns the sum of the values added, which can not overflow
+    pub fn sum(&self) -> i128 {
+        self.sum
+    }
+
+    /// mean returns the mean of the values added, or None if there are none
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.sum as f64 / self.count as f64)
+    }
+
+    /// min returns the smallest value added, or None if there are none
+    pub fn min(&self) -> Option<i64> {
+        self.min
+    }
+
+    /// max returns the largest value added, or None if there are none
+    pub fn max(&self) -> Option<i64> {
+        self.max
+    }
+
+    /// variance returns the population variance of the values added, or None if there are none
+    pub fn variance(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.m2 / self.count as f64)
+    }
+
+    /// stddev returns the population standard deviation of the values added, or None if there are
+    /// none
+    pub fn stddev(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    /// quantile estimates the `q` quantile, between zero and one, of the values added, or None if
+    /// there are none
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        self.sketch.quantile(q)
+    }
+
+    /// sketch returns the sketch used to estimate quantiles
+    pub fn sketch(&self) -> &Sketch {
+        &self.sketch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use test_util::decoder;
+    use super::{Sketch, Summary};
+
+    fn assert_close(actual: f64, expected: f64, relative_accuracy: f64) {
+        assert!((actual - expected).abs() <= expected.abs() * relative_accuracy,
+                "{} is not within {} of {}",
+                actual,
+                relative_accuracy,
+                expected);
+    }
+
+    #[test]
+    fn summary_of_range() {
+        let datapoints: Vec<DataPoint> = (0..1000)
+            .map(|i| DataPoint::new(1482892200 + i * 10, i as i64 - 200))
+            .collect();
+        let mut summary = Summary::new();
+        let mut decoder = decoder(&datapoints);
+        summary.consume_range(&mut decoder, 1482892200 + 1000, 1482892200 + 9000).unwrap();
+
+        // values -100 to 699
+        assert_eq!(summary.count(), 800);
+        assert_eq!(summary.sum(), 239600);
+        assert_eq!(summary.mean(), Some(299.5));
+        assert_eq!(summary.min(), Some(-100));
+        assert_eq!(summary.max(), Some(699));
+        assert_close(summary.stddev().unwrap(), 230.93974, 1e-6);
+
+        assert_eq!(summary.quantile(0.0).map(f64::round), Some(-100.0));
+        assert_close(summary.quantile(0.5).unwrap(), 300.0, 0.01);
+        assert_close(summary.quantile(0.99).unwrap(), 691.0, 0.01);
+        assert_eq!(summary.quantile(1.0).map(f64::round), Some(699.0));
+
+        assert_eq!(Summary::new().mean(), None);
+        assert_eq!(Summary::new().quantile(0.5), None);
+    }
+
+    #[test]
+    fn merge_summaries() {
+        // two blocks of one series and a second series
+        let series = |times: ::std::ops::Range<u64>, sign: i64| -> Vec<DataPoint> {
+            times.map(|i| DataPoint::new(i, i as i64 * sign)).collect()
+        };
+        let blocks = [series(0..500, 1), series(500..800, 1), series(0..200, -1)];
+
+        let mut merged = Summary::new();
+        let mut all = Summary::new();
+        for block in &blocks {
+            let mut summary = Summary::new();
+            summary.consume(&mut decoder(block)).unwrap();
+            merged.merge(&summary);
+
+            for dp in block {
+                all.add(*dp);
+            }
+        }
+
+        assert_eq!(merged.count(), all.count());
+        assert_eq!(merged.sum(), all.sum());
+        assert_eq!(merged.min(), Some(-199));
+        assert_eq!(merged.max(), Some(799));
+        assert_close(merged.variance().unwrap(), all.variance().unwrap(), 1e-9);
+        assert_eq!(merged.sketch(), all.sketch());
+    }
+
+    #[test]
+    fn sketch_accuracy() {
+        let mut sketch = Sketch::new(0.02);
+        let mut values: Vec<i64> = (0..10000).map(|i| (i * i) % 100003 - 5000).collect();
+        for value in &values {
+            sketch.add(*value);
+        }
+        values.sort();
+
+        for &q in &[0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let expected = values[(q * (values.len() - 1) as f64).round() as usize];
+            assert_close(sketch.quantile(q).unwrap(), expected as f64, 0.02);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// CRC32C uses the Castagnoli polynomial, in its reversed form since bytes are processed least
+// significant bit first
+const POLYNOMIAL: u32 = 0x82f63b78;
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CHECKSUM_LEN is the length, in bytes, of the checksum trailer appended to a stream
+pub const CHECKSUM_LEN: usize = 4;
+
+/// Crc32c
+///
+/// Crc32c computes a CRC32C checksum incrementally. Bits can be added one at a time, whole bytes
+/// are added to the checksum as soon as they are complete.
+#[derive(Debug, Clone)]
+pub struct Crc32c {
+    crc: u32,
+    byte: u8, // bits of the byte currently being added
+    pos: u32, // number of bits in byte
+}
+
+impl Crc32c {
+    /// new creates a new Crc32c over no bytes
+    pub fn new() -> Self {
+        Crc32c {
+            crc: !0,
+            byte: 0,
+            pos: 0,
+        }
+    }
+
+    /// update adds `bytes` to the checksum, there must be no partially added byte
+    pub fn update(&mut self, bytes: &[u8]) {
+        debug_assert_eq!(self.pos, 0);
+        for byte in bytes {
+            self.update_byte(*byte);
+        }
+    }
+
+    /// update_bits adds the bottom `num` bits of `bits` to the checksum, most significant first
+    pub fn update_bits(&mut self, bits: u64, num: u32) {
+        for i in (0..num).rev() {
+            self.byte = (self.byte << 1) | ((bits >> i) & 1) as u8;
+            self.pos += 1;
+
+            if self.pos == 8 {
+                let byte = self.byte;
+                self.update_byte(byte);
+                self.byte = 0;
+                self.pos = 0;
+            }
+        }
+    }
+
+    /// pending_bits returns the number of bits needed to complete the current byte
+    pub fn pending_bits(&self) -> u32 {
+        (8 - self.pos) % 8
+    }
+
+    /// finish returns the checksum of the complete bytes added so far
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+
+    fn update_byte(&mut self, byte: u8) {
+        self.crc = TABLE[((self.crc ^ byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+    }
+}
+
+impl Default for Crc32c {
+    fn default() -> Self {
+        Crc32c::new()
+    }
+}
+
+/// crc32c returns the CRC32C checksum of `bytes`
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    let mut c = Crc32c::new();
+    c.update(bytes);
+    c.finish()
+}
+
+/// append_checksum appends the big endian CRC32C checksum of `bytes` to them
+pub fn append_checksum(bytes: Box<[u8]>) -> Box<[u8]> {
+    let crc = crc32c(&bytes);
+    let mut bytes = bytes.into_vec();
+    bytes.extend_from_slice(&crc.to_be_bytes());
+    bytes.into_boxed_slice()
+}
+
+/// verify returns true if the last CHECKSUM_LEN bytes of `bytes` are the checksum of the rest
+pub fn verify(bytes: &[u8]) -> bool {
+    if bytes.len() < CHECKSUM_LEN {
+        return false;
+    }
+
+    let (data, trailer) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    crc32c(data).to_be_bytes() == trailer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Crc32c, crc32c, append_checksum, verify};
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn update_bits() {
+        let mut c = Crc32c::new();
+        c.update_bits(0b0011, 4);
+        assert_eq!(c.pending_bits(), 4);
+        c.update_bits(0b0001_0011_0010_0011_0011, 20);
+        c.update_bits(0x34353637, 32);
+        c.update_bits(0x3839, 16);
+        assert_eq!(c.pending_bits(), 0);
+
+        assert_eq!(c.finish(), 0xe3069283);
+    }
+
+    #[test]
+    fn verify_detects_flipped_bits() {
+        let bytes = append_checksum(b"123456789".to_vec().into_boxed_slice());
+        assert!(verify(&bytes));
+
+        for i in 0..bytes.len() * 8 {
+            let mut corrupted = bytes.to_vec();
+            corrupted[i / 8] ^= 1 << (i % 8);
+            assert!(!verify(&corrupted));
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use Bit;
+use stream::Read;
+use decode::{self, Error, Position};
+use encode::dictionary_encoder::index_width;
+use predictor::{TimestampPredictor, DeltaOfDeltaPredictor};
+
+/// DictionaryDecoder
+///
+/// DictionaryDecoder is used to decode the labels written by `DictionaryEncoder`
+#[derive(Debug)]
+pub struct DictionaryDecoder<T: Read, Q: TimestampPredictor = DeltaOfDeltaPredictor> {
+    time: u64, // current time
+    time_predictor: Q, // predicts the next time delta
+
+    labels: Vec<String>, // dictionary of labels read from the start of the block
+    width: u32, // number of bits in each label index
+    index: Option<usize>, // index of the label of the last point decoded
+
+    started: bool, // have the header and dictionary been read
+    done: bool,
+
+    count: u64, // number of points decoded
+    last_time: Option<u64>, // timestamp of the last point decoded
+
+    r: T,
+}
+
+impl<T> DictionaryDecoder<T>
+    where T: Read
+{
+    /// new creates a new DictionaryDecoder which will read bytes from r
+    pub fn new(r: T) -> Self {
+        DictionaryDecoder::with_timestamp_predictor(r, DeltaOfDeltaPredictor::new())
+    }
+}
+
+impl<T, Q> DictionaryDecoder<T, Q>
+    where T: Read,
+    Q: TimestampPredictor
+{
+    /// with_timestamp_predictor creates a new DictionaryDecoder which will read bytes from r and
+    /// uses `q` to predict the delta between timestamps, `q` must match the encoder's predictor
+    pub fn with_timestamp_predictor(r: T, q: Q) -> Self {
+        DictionaryDecoder {
+            time: 0,
+            time_predictor: q,
+            labels: Vec::new(),
+            width: 0,
+            index: None,
+            started: false,
+            done: false,
+            count: 0,
+            last_time: None,
+            r,
+        }
+    }
+
+    /// next decodes the next point, returning its timestamp and its label.
+    /// `Error::EndOfStream` is returned once every point has been decoded
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<(u64, &str), Error> {
+        if self.done {
+            return Err(Error::EndOfStream);
+        }
+
+        match self.read_point() {
+            Ok((time, index)) => {
+                self.count += 1;
+                self.last_time = Some(time);
+                Ok((time, &self.labels[index]))
+            }
+            Err(err) => {
+                self.done = true;
+                Err(err.at(self.position()))
+            }
+        }
+    }
+
+    /// labels returns the dictionary of the block, the distinct labels in the order they were
+    /// first encoded
+    pub fn labels(&mut self) -> Result<&[String], Error> {
+        if !self.started {
+            if let Err(err) = self.read_header() {
+                self.done = true;
+                return Err(err.at(self.position()));
+            }
+        }
+
+        Ok(&self.labels)
+    }
+
+    // position returns the current position in the stream, to be attached to errors
+    fn position(&self) -> Position {
+        Position {
+            bit_offset: self.r.bits_read(),
+            index: self.count,
+            last_time: self.last_time,
+        }
+    }
+
+    // read_header reads the initial timestamp and the dictionary
+    fn read_header(&mut self) -> Result<(), Error> {
+        self.started = true;
+        self.time = self.r.read_bits(64).map_err(|_| Error::InvalidInitialTimestamp)?;
+
+        let len = self.r.read_bits(32)?;
+        for _ in 0..len {
+            let label_len = self.r.read_bits(16)?;
+            let mut bytes = Vec::with_capacity(label_len as usize);
+            for _ in 0..label_len {
+                bytes.push(self.r.read_bits(8)? as u8);
+            }
+            let label = String::from_utf8(bytes).map_err(|_| Error::InvalidDictionary)?;
+            self.labels.push(label);
+        }
+        self.width = index_width(self.labels.len());
+
+        Ok(())
+    }
+
+    fn read_point(&mut self) -> Result<(u64, usize), Error> {
+        let index = match self.index {
+            None => {
+                if !self.started {
+                    self.read_header()?;
+                }
+                self.read_first_timestamp()?;
+                self.read_index()?
+            }
+            Some(index) => {
+                self.read_next_timestamp()?;
+                if self.r.read_bit()? == Bit::Zero {
+                    index
+                } else {
+                    self.read_index()?
+                }
+            }
+        };
+
+        self.index = Some(index);
+        Ok((self.time, index))
+    }
+
+    fn read_index(&mut self) -> Result<usize, Error> {
+        let index = self.r.read_bits(self.width)? as usize;
+        if index >= self.labels.len() {
+            return Err(Error::InvalidDictionary);
+        }
+        Ok(index)
+    }
+
+    fn read_first_timestamp(&mut self) -> Result<(), Error> {
+        // a stream which holds no points is followed by END_MARKER
+        decode::read_control_bit(&mut self.r)?;
+
+        let delta = self.r.read_bits(14)?;
+        self.time_predictor.update(delta);
+        self.time += delta;
+
+        Ok(())
+    }
+
+    fn read_next_timestamp(&mut self) -> Result<(), Error> {
+        let dod = decode::read_delta_of_delta(&mut self.r)?;
+        let delta = self.time_predictor.predict_next().wrapping_add(dod);
+        self.time_predictor.update(delta);
+        self.time = self.time.wrapping_add(delta);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use decode::Error;
+    use encode::dictionary_encoder::DictionaryEncoder;
+    use stream::{BufferedReader, BufferedWriter};
+    use super::DictionaryDecoder;
+
+    fn round_trip(points: &[(u64, &str)]) -> Box<[u8]> {
+        let mut e = DictionaryEncoder::new(1482268055, BufferedWriter::new());
+        for &(time, label) in points {
+            e.encode(time, label);
+        }
+        let bytes = e.close();
+
+        let mut d = DictionaryDecoder::new(BufferedReader::new(bytes.clone()));
+        for &point in points {
+            assert_eq!(d.next().unwrap(), point);
+        }
+        assert_eq!(d.next().err().unwrap(), Error::EndOfStream);
+        assert_eq!(d.next().err().unwrap(), Error::EndOfStream);
+
+        bytes
+    }
+
+    #[test]
+    fn decode_labels() {
+        // the leader of a cluster, which changes every few hundred points
+        let hosts = ["db-1.eu-west.example.com", "db-2.eu-west.example.com", "db-3.us-east"];
+        let points: Vec<(u64, &str)> = (0..5000)
+            .map(|i| (1482268065 + i * 10 + i % 3, hosts[(i as usize / 300) % hosts.len()]))
+            .collect();
+        let bytes = round_trip(&points);
+
+        // each label is stored once, and each point costs a few bits for its timestamp and one
+        // for its unchanged label
+        assert!(bytes.len() < 5000, "{} bytes", bytes.len());
+
+        // many labels, which change at every point, and non ASCII labels
+        let labels: Vec<String> = (0..300).map(|i| format!("v1.{}.{}", i % 37, i)).collect();
+        let mut points: Vec<(u64, &str)> = labels.iter()
+            .enumerate()
+            .map(|(i, label)| (1482268060 + i as u64 * 60, label.as_str()))
+            .collect();
+        points.push((1482300000, "état"));
+        points.push((1482300001, ""));
+        points.push((1482300002, "v1.0.0"));
+        round_trip(&points);
+
+        round_trip(&[(1482268060, "only")]);
+        round_trip(&[(1482268060, "only"), (1482268070, "only")]);
+    }
+
+    #[test]
+    fn decode_empty_stream() {
+        round_trip(&[]);
+
+        let e = DictionaryEncoder::new(1482268055, BufferedWriter::new());
+        let mut d = DictionaryDecoder::new(BufferedReader::new(e.close()));
+        assert!(d.labels().unwrap().is_empty());
+        assert_eq!(d.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_dictionary() {
+        let mut e = DictionaryEncoder::new(1482268055, BufferedWriter::new());
+        e.encode(1482268060, "up");
+        e.encode(1482268070, "down");
+        e.encode(1482268080, "up");
+
+        let mut d = DictionaryDecoder::new(BufferedReader::new(e.close()));
+        assert_eq!(d.labels().unwrap(), ["up", "down"]);
+        assert_eq!(d.next().unwrap(), (1482268060, "up"));
+        assert_eq!(d.labels().unwrap(), ["up", "down"]);
+    }
+
+    #[test]
+    fn decode_invalid_dictionary() {
+        // a dictionary of one label holding a byte which is not valid UTF-8
+        let bytes = vec![0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 1, 0, 1, 255, 0, 20, 0, 0];
+        let mut d = DictionaryDecoder::new(BufferedReader::new(bytes.into_boxed_slice()));
+        let err = d.next().err().unwrap();
+        assert_eq!(*err.kind(), Error::InvalidDictionary);
+        assert!(err.is_malformed());
+        assert_eq!(d.next().err().unwrap(), Error::EndOfStream);
+    }
+}
@@ -0,0 +1,200 @@
+use {Bit, DataPoint};
+use stream::Read;
+use decode::{Decode, Error, Position, read_window_xor};
+
+/// GorillaDecoder
+///
+/// GorillaDecoder is used to decode `DataPoint`s from blocks using the exact bit layout described
+/// in the Gorilla paper. Since the layout has no end of stream marker the decoder has to be told
+/// how many points the block contains. Values are stored as float64 in the block and are
+/// converted with `as i64` when decoded.
+#[derive(Debug)]
+pub struct GorillaDecoder<T: Read> {
+    time: u64, // current time
+    delta: u64, // current time delta
+    value_bits: u64, // current float value as bits
+
+    leading_zeros: u32,
+    trailing_zeros: u32,
+
+    remaining: usize, // number of DataPoints left in the block
+    index: u64, // index of the next DataPoint
+    last_time: Option<u64>, // timestamp of the last DataPoint decoded
+    first: bool, // will next DataPoint be the first DataPoint decoded
+
+    r: T,
+}
+
+impl<T> GorillaDecoder<T>
+    where T: Read
+{
+    /// new creates a new GorillaDecoder which will read `num_points` DataPoints from r
+    pub fn new(r: T, num_points: usize) -> Self {
+        GorillaDecoder {
+            time: 0,
+            delta: 0,
+            value_bits: 0,
+            leading_zeros: 0,
+            trailing_zeros: 0,
+            remaining: num_points,
+            index: 0,
+            last_time: None,
+            first: true,
+            r,
+        }
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            bit_offset: self.r.bits_read(),
+            index: self.index,
+            last_time: self.last_time,
+        }
+    }
+
+    fn read_first(&mut self) -> Result<(), Error> {
+        self.time = self.r.read_bits(64).map_err(|_| Error::InvalidInitialTimestamp)?;
+        self.delta = self.r.read_bits(14)?;
+        self.time += self.delta;
+        self.value_bits = self.r.read_bits(64)?;
+
+        Ok(())
+    }
+
+    fn read_next_timestamp(&mut self) -> Result<(), Error> {
+        let mut control_bits = 0;
+        for _ in 0..4 {
+            if self.r.read_bit()? == Bit::Zero {
+                break;
+            }
+            control_bits += 1;
+        }
+
+        let size = match control_bits {
+            0 => {
+                self.time = self.time.wrapping_add(self.delta);
+                return Ok(());
+            }
+            1 => 7,
+            2 => 9,
+            3 => 12,
+            4 => 32,
+            _ => unreachable!(),
+        };
+
+        let mut dod = self.r.read_bits(size)?;
+
+        // need to sign extend negative numbers
+        if dod > (1 << (size - 1)) {
+            dod |= u64::MAX << size;
+        }
+
+        self.delta = self.delta.wrapping_add(dod);
+        self.time = self.time.wrapping_add(self.delta);
+
+        Ok(())
+    }
+}
+
+impl<T> Decode for GorillaDecoder<T>
+    where T: Read
+{
+    fn next(&mut self) -> Result<DataPoint, Error> {
+        if self.remaining == 0 {
+            return Err(Error::EndOfStream);
+        }
+
+        let result = if self.first {
+            self.read_first()
+        } else {
+            self.read_next_timestamp().and_then(|_| {
+                self.value_bits ^= read_window_xor(&mut self.r,
+                                                   &mut self.leading_zeros,
+                                                   &mut self.trailing_zeros)?;
+                Ok(())
+            })
+        };
+        if let Err(err) = result {
+            return Err(err.at(self.position()));
+        }
+
+        self.first = false;
+        self.remaining -= 1;
+        self.index += 1;
+        self.last_time = Some(self.time);
+
+        Ok(DataPoint::new(self.time, f64::from_bits(self.value_bits) as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Bit, DataPoint, Decode, Encode};
+    use stream::{BufferedReader, BufferedWriter, Write};
+    use decode::Error;
+    use encode::gorilla_encoder::GorillaEncoder;
+    use super::GorillaDecoder;
+
+    #[test]
+    fn decode_paper_example() {
+        // the example block from figure 2 of the Gorilla paper
+        let bytes = vec![0, 0, 0, 0, 85, 16, 197, 32, 0, 249, 0, 160, 0, 0, 0, 0, 0, 2, 252, 107,
+                         6];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let mut decoder = GorillaDecoder::new(r, 3);
+
+        let start_time = 1427162400; // 2015-03-24T02:00:00+00:00
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start_time + 62, 12));
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start_time + 122, 12));
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start_time + 182, 24));
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn round_trip() {
+        let start_time = 1427162400; // 2015-03-24T02:00:00+00:00
+        let datapoints = vec![DataPoint::new(start_time + 62, 12),
+                              DataPoint::new(start_time + 122, -3),
+                              DataPoint::new(start_time + 182, 4096),
+                              DataPoint::new(start_time + 250, 4097),
+                              DataPoint::new(start_time + 500, 1 << 40),
+                              DataPoint::new(start_time + 5000, 0),
+                              DataPoint::new(start_time + 5001, -1 << 52),
+                              DataPoint::new(start_time + 5003, -1 << 52)];
+
+        let mut e = GorillaEncoder::new(start_time, BufferedWriter::new());
+        for dp in &datapoints {
+            e.encode(*dp);
+        }
+
+        let r = BufferedReader::new(e.close());
+        let mut decoder = GorillaDecoder::new(r, datapoints.len());
+        for dp in datapoints {
+            assert_eq!(decoder.next().unwrap(), dp);
+        }
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_invalid_xor_window() {
+        let start_time = 1427162400; // 2015-03-24T02:00:00+00:00
+        let mut w = BufferedWriter::new();
+        w.write_bits(start_time, 64);
+        w.write_bits(62, 14);
+        w.write_bits(0, 64);
+
+        // the second value has 31 leading zeros and 63 significant digits, 94 bits in total
+        w.write_bit(Bit::Zero);
+        w.write_bits(0b11, 2);
+        w.write_bits(31, 5);
+        w.write_bits(63, 6);
+
+        let r = BufferedReader::new(w.close());
+        let mut decoder = GorillaDecoder::new(r, 2);
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start_time + 62, 0));
+
+        let err = decoder.next().err().unwrap();
+        assert_eq!(*err.kind(), Error::InvalidXorWindow);
+        assert!(err.is_malformed());
+    }
+}
@@ -0,0 +1,83 @@
+use DataPoint;
+use decode::{self, Decode, Error};
+
+/// Interpolator
+///
+/// Interpolator reconstructs the value of a series at any time by interpolating linearly between
+/// the `DataPoint`s decoded on either side of it. It is the decode side of the filtering done by
+/// `DeadbandEncoder` and `SwingingDoorEncoder`, which keep just enough `DataPoint`s for the ones
+/// they drop to be reconstructed this way.
+#[derive(Debug)]
+pub struct Interpolator<D: Decode> {
+    decoder: D,
+    previous: Option<DataPoint>, // last DataPoint at or before the last time asked for
+    next: Option<DataPoint>, // first DataPoint after the last time asked for
+    started: bool, // has the first DataPoint been read
+    done: bool, // has the end of the stream been reached
+}
+
+impl<D> Interpolator<D>
+    where D: Decode
+{
+    /// new creates a new Interpolator of the `DataPoint`s decoded by `decoder`
+    pub fn new(decoder: D) -> Self {
+        Interpolator {
+            decoder,
+            previous: None,
+            next: None,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// value_at returns the value of the series at `time`, or None if `time` is before the first
+    /// `DataPoint` or after the last. Times must be asked for in order, since `DataPoint`s are
+    /// only decoded once
+    pub fn value_at(&mut self, time: u64) -> Result<Option<f64>, Error> {
+        Ok(match self.neighbours(time)? {
+            (Some(previous), _) if previous.time == time => Some(previous.value as f64),
+            (Some(previous), Some(next)) => Some(interpolate(previous, next, time)),
+            _ => None,
+        })
+    }
+
+    /// neighbours returns the last `DataPoint` at or before `time` and the first `DataPoint`
+    /// after it, either of which is None if there is no such `DataPoint`. Times must be asked
+    /// for in order, since `DataPoint`s are only decoded once
+    pub fn neighbours(&mut self,
+                      time: u64)
+                      -> Result<(Option<DataPoint>, Option<DataPoint>), Error> {
+        if !self.started {
+            self.next = self.read()?;
+            self.started = true;
+        }
+        assert!(self.previous.is_none_or(|previous| previous.time <= time),
+                "times must be asked for in order");
+
+        while let Some(next) = self.next.filter(|next| next.time <= time) {
+            self.previous = Some(next);
+            self.next = self.read()?;
+        }
+
+        Ok((self.previous, self.next))
+    }
+
+    // read reads the next DataPoint from the decoder, or None at the end of the stream
+    fn read(&mut self) -> Result<Option<DataPoint>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let dp = decode::next_datapoint(&mut self.decoder)?;
+        self.done = dp.is_none();
+        Ok(dp)
+    }
+}
+
+/// interpolate returns the value at `time` of the line between `previous` and `next`, `time` must
+/// be between their timestamps
+pub fn interpolate(previous: DataPoint, next: DataPoint, time: u64) -> f64 {
+    let fraction = (time - previous.time) as f64 / (next.time - previous.time) as f64;
+    let delta = next.value as f64 - previous.value as f64;
+    previous.value as f64 + delta * fraction
+}
@@ -0,0 +1,538 @@
+use {Bit, DataPoint};
+use stream::Read;
+use decode::{Decode, Error, Position};
+use encode::m3tsz_encoder::*;
+
+/// M3TszDecoder
+///
+/// M3TszDecoder is used to decode `DataPoint`s from streams in the M3TSZ format. Times are
+/// returned in nanoseconds and values, which M3 stores as float64, are converted with `as i64`.
+#[derive(Debug)]
+pub struct M3TszDecoder<T: Read> {
+    time: u64, // current time
+    delta: i64, // current time delta
+    time_unit: Option<TimeUnit>, // current time unit
+    default_unit: TimeUnit, // time unit the stream starts with
+    unit_changed: bool, // has the time unit changed since the last DataPoint
+    annotation: Option<Vec<u8>>, // annotation of the last DataPoint decoded
+
+    int_optimized: bool,
+    is_float: bool, // is the current value stored as a float
+    int_val: f64, // current integer value
+    sig: u8,
+    mult: u8,
+
+    prev_float_bits: u64, // current float value as bits
+    prev_xor: u64,
+
+    first: bool, // will next DataPoint be the first DataPoint decoded
+    done: bool,
+    index: u64, // index of the next DataPoint
+    last_time: Option<u64>, // timestamp of the last DataPoint decoded
+
+    r: T,
+}
+
+impl<T> M3TszDecoder<T>
+    where T: Read
+{
+    /// new creates a new M3TszDecoder for a stream written with integer optimisation enabled
+    /// and whose default time unit is `unit`
+    pub fn new(r: T, unit: TimeUnit) -> Self {
+        M3TszDecoder::with_int_optimization(r, unit, true)
+    }
+
+    /// with_int_optimization creates a new M3TszDecoder, like `new`, for a stream which only
+    /// uses the integer optimisation if `int_optimized` is true
+    pub fn with_int_optimization(r: T, unit: TimeUnit, int_optimized: bool) -> Self {
+        M3TszDecoder {
+            time: 0,
+            delta: 0,
+            time_unit: None,
+            default_unit: unit,
+            unit_changed: false,
+            annotation: None,
+            int_optimized,
+            is_float: false,
+            int_val: 0.0,
+            sig: 0,
+            mult: 0,
+            prev_float_bits: 0,
+            prev_xor: 0,
+            first: true,
+            done: false,
+            index: 0,
+            last_time: None,
+            r,
+        }
+    }
+
+    /// time_unit returns the time unit of the last DataPoint decoded
+    pub fn time_unit(&self) -> Option<TimeUnit> {
+        self.time_unit
+    }
+
+    /// annotation returns the annotation written with the last DataPoint decoded, if any
+    pub fn annotation(&self) -> Option<&[u8]> {
+        self.annotation.as_ref().map(|a| &a[..])
+    }
+
+    // read_marker handles any special markers before the next delta of delta, returning
+    // EndOfStream if the stream is finished
+    fn read_markers(&mut self) -> Result<(), Error> {
+        loop {
+            let len = MARKER_OPCODE_LEN + MARKER_VALUE_LEN;
+            let bits = match self.r.peak_bits(len) {
+                Ok(bits) => bits,
+                Err(_) => return Ok(()),
+            };
+
+            if bits >> MARKER_VALUE_LEN != MARKER_OPCODE {
+                return Ok(());
+            }
+
+            match bits & ((1 << MARKER_VALUE_LEN) - 1) {
+                END_OF_STREAM_MARKER => {
+                    self.r.read_bits(len)?;
+                    return Err(Error::EndOfStream);
+                }
+                ANNOTATION_MARKER => {
+                    self.r.read_bits(len)?;
+                    self.read_annotation()?;
+                }
+                TIME_UNIT_MARKER => {
+                    self.r.read_bits(len)?;
+                    let unit = TimeUnit::from_u8(self.r.read_byte()?);
+                    if unit.is_none() {
+                        return Err(Error::InvalidTimeUnit);
+                    }
+                    if unit != self.time_unit {
+                        self.unit_changed = true;
+                    }
+                    self.time_unit = unit;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_annotation(&mut self) -> Result<(), Error> {
+        let mut ux = 0u64;
+        let mut shift = 0;
+        for _ in 0..10 {
+            let byte = self.r.read_byte()? as u64;
+            ux |= (byte & 0x7f) << shift;
+            if byte < 0x80 {
+                break;
+            }
+            shift += 7;
+        }
+
+        // the length is stored minus one as a zig-zag varint
+        let mut len = (ux >> 1) as i64;
+        if ux & 1 != 0 {
+            len = !len;
+        }
+
+        // annotations are never empty, and a corrupt length must not overflow
+        let len = match len.checked_add(1) {
+            Some(len) if len > 0 => len,
+            _ => return Err(Error::InvalidAnnotation),
+        };
+
+        let mut annotation = Vec::new();
+        for _ in 0..len {
+            annotation.push(self.r.read_byte()?);
+        }
+        self.annotation = Some(annotation);
+
+        Ok(())
+    }
+
+    fn read_delta_of_delta(&mut self) -> Result<i64, Error> {
+        if self.unit_changed {
+            // when the time unit changes the delta of delta is written in nanoseconds
+            return Ok(self.r.read_bits(64)? as i64);
+        }
+
+        let unit = self.time_unit.ok_or(Error::InvalidTimeUnit)?;
+
+        if self.r.read_bit()? == Bit::Zero {
+            return Ok(0);
+        }
+
+        let (bucket_bits, default_bits) = unit.bucket_bits();
+        let mut size = default_bits;
+        for bits in &bucket_bits {
+            if self.r.read_bit()? == Bit::Zero {
+                size = *bits;
+                break;
+            }
+        }
+
+        let mut dod = self.r.read_bits(size)?;
+
+        // need to sign extend negative numbers
+        if size < 64 && dod >> (size - 1) == 1 {
+            dod |= u64::MAX << size;
+        }
+
+        Ok((dod as i64).wrapping_mul(unit.nanos() as i64))
+    }
+
+    fn read_next_timestamp(&mut self) -> Result<u64, Error> {
+        self.annotation = None;
+        self.read_markers()?;
+
+        let dod = self.read_delta_of_delta()?;
+        self.delta = self.delta.wrapping_add(dod);
+        self.time = self.time.wrapping_add(self.delta as u64);
+
+        // the encoder resets the delta whenever the time unit changes
+        if self.unit_changed {
+            self.delta = 0;
+            self.unit_changed = false;
+        }
+
+        Ok(self.time)
+    }
+
+    fn read_first_value(&mut self) -> Result<(), Error> {
+        if !self.int_optimized || self.r.read_bits(1)? == OPCODE_FLOAT_MODE {
+            self.is_float = true;
+            return self.read_full_float();
+        }
+
+        self.read_int_sig_mult()?;
+        self.read_int_val_diff()
+    }
+
+    fn read_next_value(&mut self) -> Result<(), Error> {
+        if !self.int_optimized {
+            return self.read_next_float();
+        }
+
+        if self.r.read_bits(1)? == OPCODE_UPDATE {
+            if self.r.read_bits(1)? == OPCODE_REPEAT {
+                return Ok(());
+            }
+
+            if self.r.read_bits(1)? == OPCODE_FLOAT_MODE {
+                self.is_float = true;
+                return self.read_full_float();
+            }
+
+            self.read_int_sig_mult()?;
+            self.is_float = false;
+            return self.read_int_val_diff();
+        }
+
+        if self.is_float {
+            self.read_next_float()
+        } else {
+            self.read_int_val_diff()
+        }
+    }
+
+    fn read_int_sig_mult(&mut self) -> Result<(), Error> {
+        if self.r.read_bits(1)? == OPCODE_UPDATE_SIG {
+            if self.r.read_bits(1)? == OPCODE_ZERO_SIG {
+                self.sig = 0;
+            } else {
+                self.sig = self.r.read_bits(NUM_SIG_BITS)? as u8 + 1;
+            }
+        }
+
+        if self.r.read_bits(1)? == OPCODE_UPDATE_MULT {
+            self.mult = self.r.read_bits(NUM_MULT_BITS)? as u8;
+            if self.mult > MAX_MULT {
+                return Err(Error::InvalidMultiplier);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_int_val_diff(&mut self) -> Result<(), Error> {
+        let sign = if self.r.read_bits(1)? == OPCODE_NEGATIVE {
+            1.0
+        } else {
+            -1.0
+        };
+
+        let diff = self.r.read_bits(self.sig as u32)?;
+        self.int_val += sign * diff as f64;
+
+        Ok(())
+    }
+
+    fn read_full_float(&mut self) -> Result<(), Error> {
+        self.prev_float_bits = self.r.read_bits(64)?;
+        self.prev_xor = self.prev_float_bits;
+
+        Ok(())
+    }
+
+    fn read_next_float(&mut self) -> Result<(), Error> {
+        if self.r.read_bits(1)? == OPCODE_ZERO_VALUE_XOR {
+            self.prev_xor = 0;
+            return Ok(());
+        }
+
+        if self.r.read_bits(1)? | 0b10 == OPCODE_CONTAINED_VALUE_XOR {
+            let (leading, trailing) = leading_and_trailing_zeros(self.prev_xor);
+            let bits = self.r.read_bits(64 - leading - trailing)?;
+            self.prev_xor = bits << trailing;
+        } else {
+            let leading = self.r.read_bits(6)? as u32;
+            let meaningful_bits = self.r.read_bits(6)? as u32 + 1;
+            let trailing = 64u32.checked_sub(leading + meaningful_bits)
+                .ok_or(Error::InvalidXorWindow)?;
+            let bits = self.r.read_bits(meaningful_bits)?;
+            self.prev_xor = bits << trailing;
+        }
+
+        self.prev_float_bits ^= self.prev_xor;
+
+        Ok(())
+    }
+
+    fn value(&self) -> f64 {
+        if self.is_float {
+            return f64::from_bits(self.prev_float_bits);
+        }
+
+        self.int_val / 10f64.powi(self.mult as i32)
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            bit_offset: self.r.bits_read(),
+            index: self.index,
+            last_time: self.last_time,
+        }
+    }
+
+    fn read_datapoint(&mut self) -> Result<DataPoint, Error> {
+        if self.done {
+            return Err(Error::EndOfStream);
+        }
+
+        if self.first {
+            self.time = self.r.read_bits(64).map_err(|_| Error::InvalidInitialTimestamp)?;
+            self.time_unit = TimeUnit::initial(self.time, self.default_unit);
+        }
+
+        let time = self.read_next_timestamp()
+            .map_err(|err| {
+                if err == Error::EndOfStream {
+                    self.done = true;
+                }
+                err
+            })?;
+
+        if self.first {
+            self.first = false;
+            self.read_first_value()?;
+        } else {
+            self.read_next_value()?;
+        }
+
+        Ok(DataPoint::new(time, self.value() as i64))
+    }
+}
+
+impl<T> Decode for M3TszDecoder<T>
+    where T: Read
+{
+    fn next(&mut self) -> Result<DataPoint, Error> {
+        match self.read_datapoint() {
+            Ok(dp) => {
+                self.index += 1;
+                self.last_time = Some(dp.time);
+                Ok(dp)
+            }
+            Err(err) => Err(err.at(self.position())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Bit, DataPoint, Decode, Encode};
+    use stream::{BufferedReader, BufferedWriter, Write};
+    use decode::{self, Error};
+    use encode::m3tsz_encoder::{M3TszEncoder, TimeUnit, ANNOTATION_MARKER, MARKER_OPCODE,
+                                MARKER_OPCODE_LEN, MARKER_VALUE_LEN};
+    use super::M3TszDecoder;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn decode_all<T: ::stream::Read>(decoder: &mut M3TszDecoder<T>) -> Vec<DataPoint> {
+        decode::decode_all(decoder).expect("Received an error from decoder")
+    }
+
+    #[test]
+    fn create_new_decoder() {
+        let start = 1427162400 * SECOND;
+        let e = M3TszEncoder::new(start, TimeUnit::Second, BufferedWriter::new());
+
+        let r = BufferedReader::new(e.close());
+        let mut decoder = M3TszDecoder::new(r, TimeUnit::Second);
+
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn round_trip_mixed_values() {
+        let start = 1427162400 * SECOND;
+        // values are converted to float64, so the jumps between large values which switch the
+        // encoder between integers and floats are chosen to be exactly representable
+        let values = [12, 12, 13, -1000, 0, 0, 1 << 40, -(1 << 62), 1 << 62, 1 << 62, 1 << 52,
+                      (1 << 52) + 1, 7, 8, 9, 10, 11, 12, 13, 14, -(1 << 62), -(1 << 62),
+                      1 << 62, 1 << 61, -(1 << 61)];
+
+        let datapoints: Vec<DataPoint> = values.iter()
+            .enumerate()
+            .map(|(i, v)| DataPoint::new(start + (i as u64 * 10 + i as u64 % 3) * SECOND, *v))
+            .collect();
+
+        for int_optimized in &[true, false] {
+            let w = BufferedWriter::new();
+            let mut e = M3TszEncoder::with_int_optimization(start, TimeUnit::Second,
+                                                            *int_optimized, w);
+            for dp in &datapoints {
+                e.encode(*dp);
+            }
+
+            let r = BufferedReader::new(e.close());
+            let mut decoder = M3TszDecoder::with_int_optimization(r, TimeUnit::Second,
+                                                                  *int_optimized);
+            assert_eq!(decode_all(&mut decoder), datapoints);
+        }
+    }
+
+    #[test]
+    fn time_unit_changes_and_annotations() {
+        let start = 1427162400 * SECOND;
+        let mut e = M3TszEncoder::new(start, TimeUnit::Second, BufferedWriter::new());
+
+        e.encode_with(DataPoint::new(start + 10 * SECOND, 1), TimeUnit::Second, Some(b"a"));
+        e.encode_with(DataPoint::new(start + 20 * SECOND, 2), TimeUnit::Second, Some(b"a"));
+        e.encode_with(DataPoint::new(start + 20 * SECOND + 5_000_000, 3),
+                      TimeUnit::Millisecond,
+                      Some(b"bc"));
+        // deltas of delta below 8ms are read back as markers, as in M3
+        e.encode_with(DataPoint::new(start + 20 * SECOND + 25_000_000, 4),
+                      TimeUnit::Millisecond,
+                      None);
+        e.encode_with(DataPoint::new(start + 20 * SECOND + 25_000_123, 5),
+                      TimeUnit::Nanosecond,
+                      None);
+        e.encode_with(DataPoint::new(start + 40 * SECOND, 6), TimeUnit::Second, Some(b"a"));
+
+        let r = BufferedReader::new(e.close());
+        let mut decoder = M3TszDecoder::new(r, TimeUnit::Second);
+
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start + 10 * SECOND, 1));
+        assert_eq!(decoder.annotation(), Some(&b"a"[..]));
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start + 20 * SECOND, 2));
+        assert_eq!(decoder.annotation(), None);
+        assert_eq!(decoder.next().unwrap(),
+                   DataPoint::new(start + 20 * SECOND + 5_000_000, 3));
+        assert_eq!(decoder.annotation(), Some(&b"bc"[..]));
+        assert_eq!(decoder.time_unit(), Some(TimeUnit::Millisecond));
+        assert_eq!(decoder.next().unwrap(),
+                   DataPoint::new(start + 20 * SECOND + 25_000_000, 4));
+        assert_eq!(decoder.next().unwrap(),
+                   DataPoint::new(start + 20 * SECOND + 25_000_123, 5));
+        assert_eq!(decoder.time_unit(), Some(TimeUnit::Nanosecond));
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start + 40 * SECOND, 6));
+        assert_eq!(decoder.annotation(), Some(&b"a"[..]));
+        assert_eq!(decoder.time_unit(), Some(TimeUnit::Second));
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn encode_uses_default_unit() {
+        let start = 1427162400 * SECOND;
+        let mut e = M3TszEncoder::new(start, TimeUnit::Second, BufferedWriter::new());
+
+        e.encode_with(DataPoint::new(start + 5_000_000, 1), TimeUnit::Millisecond, None);
+        e.encode(DataPoint::new(start + 10 * SECOND, 2));
+
+        let r = BufferedReader::new(e.close());
+        let mut decoder = M3TszDecoder::new(r, TimeUnit::Second);
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start + 5_000_000, 1));
+        assert_eq!(decoder.time_unit(), Some(TimeUnit::Millisecond));
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start + 10 * SECOND, 2));
+        assert_eq!(decoder.time_unit(), Some(TimeUnit::Second));
+    }
+
+    #[test]
+    fn start_not_multiple_of_time_unit() {
+        let start = 1427162400 * SECOND + 1;
+        let mut e = M3TszEncoder::new(start, TimeUnit::Second, BufferedWriter::new());
+
+        let datapoints = vec![DataPoint::new(start + 10 * SECOND - 1, 1),
+                              DataPoint::new(start + 20 * SECOND - 1, 2)];
+        for dp in &datapoints {
+            e.encode(*dp);
+        }
+
+        let r = BufferedReader::new(e.close());
+        let mut decoder = M3TszDecoder::new(r, TimeUnit::Second);
+        assert_eq!(decode_all(&mut decoder), datapoints);
+    }
+
+    #[test]
+    fn invalid_annotation_length() {
+        let start = 1427162400 * SECOND;
+
+        // zig-zag varints of a negative length and of a length which overflows when one is added
+        for &ux in &[9, (i64::MAX as u64) << 1] {
+            let mut w = BufferedWriter::new();
+            w.write_bits(start, 64);
+            w.write_bits(MARKER_OPCODE, MARKER_OPCODE_LEN);
+            w.write_bits(ANNOTATION_MARKER, MARKER_VALUE_LEN);
+            let mut ux: u64 = ux;
+            while ux >= 0x80 {
+                w.write_byte((ux as u8) | 0x80);
+                ux >>= 7;
+            }
+            w.write_byte(ux as u8);
+            w.write_bits(0, 64);
+
+            let mut decoder = M3TszDecoder::new(BufferedReader::new(w.close()), TimeUnit::Second);
+            let err = decoder.next().err().unwrap();
+            assert_eq!(*err.kind(), Error::InvalidAnnotation);
+            assert!(err.is_malformed());
+        }
+    }
+
+    #[test]
+    fn invalid_xor_window() {
+        let start = 1427162400 * SECOND;
+        let mut w = BufferedWriter::new();
+        w.write_bits(start, 64);
+        w.write_bit(Bit::Zero);
+        w.write_bits(0, 64);
+
+        // the second value has 63 leading zeros and 64 meaningful bits, 127 bits in total
+        w.write_bit(Bit::Zero);
+        w.write_bit(Bit::One);
+        w.write_bit(Bit::One);
+        w.write_bits(63, 6);
+        w.write_bits(63, 6);
+        w.write_bits(0, 64);
+
+        let r = BufferedReader::new(w.close());
+        let mut decoder = M3TszDecoder::with_int_optimization(r, TimeUnit::Second, false);
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(start, 0));
+
+        let err = decoder.next().err().unwrap();
+        assert_eq!(*err.kind(), Error::InvalidXorWindow);
+        assert!(err.is_malformed());
+    }
+}
@@ -1,6 +1,32 @@
 use std::{error, fmt};
-use DataPoint;
-use stream;
+use {Bit, DataPoint};
+use stream::{self, Read};
+use encode::std_encoder::{END_MARKER, END_MARKER_LEN};
+
+/// Position
+///
+/// Position describes where in a stream a decoder failed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Position {
+    /// The number of bits read from the stream when the error occurred.
+    pub bit_offset: u64,
+
+    /// The index of the `DataPoint` being decoded.
+    pub index: u64,
+
+    /// The timestamp of the last `DataPoint` decoded successfully, if there was one.
+    pub last_time: Option<u64>,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bit {}, DataPoint {}", self.bit_offset, self.index)?;
+        if let Some(time) = self.last_time {
+            write!(f, ", last timestamp {}", time)?;
+        }
+        Ok(())
+    }
+}
 
 /// Error
 ///
@@ -10,7 +36,64 @@ pub enum Error {
     Stream(stream::Error),
     InvalidInitialTimestamp,
     InvalidEndOfStream,
+    InvalidTimeUnit,
+    InvalidMultiplier,
+    InvalidAnnotation,
+    InvalidStreamLength,
+    ChecksumMismatch,
+    InvalidSyncPoint,
+    /// InvalidDictionary is returned when the dictionary of a block of labels holds a label which
+    /// is not valid UTF-8, or a label's index is not in the dictionary
+    InvalidDictionary,
+    /// InvalidXorWindow is returned when the leading zeros and length of the significant digits of
+    /// an xor add up to more than 64 bits
+    InvalidXorWindow,
+    /// PointsLost is returned by a recovering decoder after it skips a damaged part of the stream,
+    /// the `DataPoint`s from the first index up to, but not including, the second were lost
+    PointsLost(u64, u64),
     EndOfStream,
+    /// At wraps an error with the position in the stream where it occurred
+    At(Position, Box<Error>),
+}
+
+impl Error {
+    /// at attaches the position in the stream where the error occurred, `EndOfStream` and
+    /// `PointsLost` are not failures so they are returned unchanged
+    pub fn at(self, position: Position) -> Error {
+        match self {
+            Error::EndOfStream | Error::PointsLost(..) | Error::At(..) => self,
+            err => Error::At(position, Box::new(err)),
+        }
+    }
+
+    /// kind returns the error without the position it occurred at
+    pub fn kind(&self) -> &Error {
+        match *self {
+            Error::At(_, ref err) => err,
+            ref err => err,
+        }
+    }
+
+    /// position returns where in the stream the error occurred, if it is known
+    pub fn position(&self) -> Option<Position> {
+        match *self {
+            Error::At(position, _) => Some(position),
+            _ => None,
+        }
+    }
+
+    /// is_truncated returns true if the stream ended before the decoder expected it to
+    pub fn is_truncated(&self) -> bool {
+        matches!(*self.kind(), Error::Stream(stream::Error::EOF) | Error::InvalidInitialTimestamp)
+    }
+
+    /// is_malformed returns true if the stream contained bits the decoder could not interpret
+    pub fn is_malformed(&self) -> bool {
+        matches!(*self.kind(),
+                 Error::InvalidEndOfStream | Error::InvalidTimeUnit | Error::InvalidMultiplier |
+                 Error::InvalidAnnotation | Error::InvalidStreamLength | Error::ChecksumMismatch |
+                 Error::InvalidSyncPoint | Error::InvalidDictionary | Error::InvalidXorWindow)
+    }
 }
 
 impl fmt::Display for Error {
@@ -19,18 +102,47 @@ impl fmt::Display for Error {
             Error::Stream(ref err) => write!(f, "Stream error: {}", err),
             Error::InvalidInitialTimestamp => write!(f, "Failed to parse intitial timestamp"),
             Error::InvalidEndOfStream => write!(f, "Encountered invalid end of steam marker"),
+            Error::InvalidTimeUnit => write!(f, "Encountered invalid time unit"),
+            Error::InvalidMultiplier => write!(f, "Encountered invalid value multiplier"),
+            Error::InvalidAnnotation => write!(f, "Encountered invalid annotation length"),
+            Error::InvalidStreamLength => write!(f, "Stream length did not match its header"),
+            Error::ChecksumMismatch => write!(f, "Stream checksum did not match its contents"),
+            Error::InvalidSyncPoint => write!(f, "Encountered invalid sync point"),
+            Error::InvalidDictionary => write!(f, "Encountered invalid label dictionary"),
+            Error::InvalidXorWindow => write!(f, "Encountered invalid xor window"),
+            Error::PointsLost(start, end) => write!(f, "Lost DataPoints {} to {}", start, end),
             Error::EndOfStream => write!(f, "Encountered end of the stream"),
+            Error::At(ref position, ref err) => write!(f, "{} ({})", err, position),
         }
     }
 }
 
 impl error::Error for Error {
+    #[allow(deprecated)]
     fn description(&self) -> &str {
         match *self {
             Error::Stream(ref err) => err.description(),
             Error::InvalidInitialTimestamp => "Failed to parse initial timestamp",
             Error::InvalidEndOfStream => "Encountered invalid end of steam marker",
+            Error::InvalidTimeUnit => "Encountered invalid time unit",
+            Error::InvalidMultiplier => "Encountered invalid value multiplier",
+            Error::InvalidAnnotation => "Encountered invalid annotation length",
+            Error::InvalidStreamLength => "Stream length did not match its header",
+            Error::ChecksumMismatch => "Stream checksum did not match its contents",
+            Error::InvalidSyncPoint => "Encountered invalid sync point",
+            Error::InvalidDictionary => "Encountered invalid label dictionary",
+            Error::InvalidXorWindow => "Encountered invalid xor window",
+            Error::PointsLost(..) => "Lost DataPoints in a damaged part of the stream",
             Error::EndOfStream => "Encountered end of the stream",
+            Error::At(_, ref err) => err.description(),
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Stream(ref err) => Some(err),
+            Error::At(_, ref err) => Some(&**err),
+            _ => None,
         }
     }
 }
@@ -48,4 +160,170 @@ pub trait Decode {
     fn next(&mut self) -> Result<DataPoint, Error>;
 }
 
-pub mod std_decoder;
\ No newline at end of file
+/// next_datapoint returns the next `DataPoint` from `decoder`, or None once the end of its stream
+/// has been reached
+pub fn next_datapoint<D: Decode>(decoder: &mut D) -> Result<Option<DataPoint>, Error> {
+    match decoder.next() {
+        Ok(dp) => Ok(Some(dp)),
+        Err(Error::EndOfStream) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// for_each calls `f` with each `DataPoint` read from `decoder` until the end of its stream, or
+/// until `f` returns false
+pub fn for_each<D, F>(decoder: &mut D, mut f: F) -> Result<(), Error>
+    where D: Decode,
+          F: FnMut(DataPoint) -> bool
+{
+    while let Some(dp) = next_datapoint(decoder)? {
+        if !f(dp) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// decode_all decodes every `DataPoint` read from `decoder` until the end of its stream
+pub fn decode_all<D: Decode>(decoder: &mut D) -> Result<Vec<DataPoint>, Error> {
+    let mut datapoints = Vec::new();
+    for_each(decoder, |dp| {
+        datapoints.push(dp);
+        true
+    })?;
+    Ok(datapoints)
+}
+
+/// read_control_bit reads the zero control bit which comes before the first `DataPoint` of a
+/// stream, or before each run of `StateEncoder`. If a one bit follows instead the stream is
+/// followed by END_MARKER and `Error::EndOfStream` is returned once it has been read
+pub fn read_control_bit<T: Read>(r: &mut T) -> Result<(), Error> {
+    if r.peak_bits(1)? == 1 {
+        return match r.read_bits(END_MARKER_LEN)? {
+            END_MARKER => Err(Error::EndOfStream),
+            _ => Err(Error::InvalidEndOfStream),
+        };
+    }
+    r.read_bit()?;
+
+    Ok(())
+}
+
+/// read_delta_of_delta reads a delta of delta written by `encode::write_delta_of_delta`, sign
+/// extended to 64 bits so that it can be added to the predicted delta with `wrapping_add`.
+/// `Error::EndOfStream` is returned if END_MARKER is read instead
+pub fn read_delta_of_delta<T: Read>(r: &mut T) -> Result<u64, Error> {
+    let mut control_bits = 0;
+    for _ in 0..4 {
+        if r.read_bit()? == Bit::Zero {
+            break;
+        }
+        control_bits += 1;
+    }
+
+    let size = match control_bits {
+        0 => return Ok(0),
+        1 => 7,
+        2 => 9,
+        3 => 12,
+        _ => 32,
+    };
+
+    let mut dod = r.read_bits(size)?;
+
+    // a delta of delta of zero can never be stored in 32 bits so it marks the end of the stream
+    if size == 32 && dod == 0 {
+        return Err(Error::EndOfStream);
+    }
+
+    // sign extend negative numbers
+    if dod > (1 << (size - 1)) {
+        dod |= u64::MAX << size;
+    }
+
+    Ok(dod)
+}
+
+/// read_xor reads an xor written by `encode::write_xor`, `leading_zeros` holds the number of
+/// leading zeros last read
+pub fn read_xor<T: Read>(r: &mut T, leading_zeros: &mut u32) -> Result<u64, Error> {
+    if r.read_bit()? == Bit::Zero {
+        return Ok(0);
+    }
+
+    if r.read_bit()? == Bit::One {
+        *leading_zeros = r.read_bits(6)? as u32;
+    }
+
+    Ok(r.read_bits(64 - *leading_zeros)?)
+}
+
+/// read_window_xor reads an xor written by `encode::write_window_xor`, shifted back into place.
+/// `leading_zeros` and `trailing_zeros` hold the last window read
+pub(crate) fn read_window_xor<T: Read>(r: &mut T,
+                                       leading_zeros: &mut u32,
+                                       trailing_zeros: &mut u32)
+                                       -> Result<u64, Error> {
+    if r.read_bit()? == Bit::Zero {
+        return Ok(0);
+    }
+
+    if r.read_bit()? == Bit::One {
+        *leading_zeros = r.read_bits(5)? as u32;
+
+        // significant digits of 64 are stored as 0
+        let mut significant_digits = r.read_bits(6)? as u32;
+        if significant_digits == 0 {
+            significant_digits = 64;
+        }
+        *trailing_zeros = 64u32.checked_sub(*leading_zeros)
+            .and_then(|n| n.checked_sub(significant_digits))
+            .ok_or(Error::InvalidXorWindow)?;
+    }
+
+    let size = 64 - *leading_zeros - *trailing_zeros;
+    let bits = r.read_bits(size)?;
+
+    Ok(bits << *trailing_zeros)
+}
+
+pub mod std_decoder;
+pub mod prometheus_decoder;
+pub mod gorilla_decoder;
+pub mod m3tsz_decoder;
+pub mod multi_decoder;
+pub mod state_decoder;
+pub mod dictionary_decoder;
+pub mod interpolate;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as StdError;
+
+    use stream;
+    use super::{Error, Position};
+
+    #[test]
+    fn error_position() {
+        let position = Position {
+            bit_offset: 1234,
+            index: 5,
+            last_time: Some(1482268055),
+        };
+        let err = Error::Stream(stream::Error::EOF).at(position);
+
+        assert_eq!(err.position(), Some(position));
+        assert_eq!(*err.kind(), Error::Stream(stream::Error::EOF));
+        assert_eq!(err.to_string(),
+                   "Stream error: Encountered the end of the stream (bit 1234, DataPoint 5, last \
+                    timestamp 1482268055)");
+
+        // the source chain leads from the position down to the stream error
+        let source = err.source().unwrap();
+        assert_eq!(source.to_string(), "Stream error: Encountered the end of the stream");
+        assert_eq!(source.source().unwrap().to_string(), "Encountered the end of the stream");
+
+        // the end of the stream is not a failure so has no position
+        assert_eq!(Error::EndOfStream.at(position), Error::EndOfStream);
+    }
+}
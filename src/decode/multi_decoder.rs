@@ -0,0 +1,241 @@
+use stream::Read;
+use decode::{self, Error, Position};
+use predictor::{Predictor, TimestampPredictor, DeltaOfDeltaPredictor};
+
+// Column holds the state needed to decode the values of one field
+#[derive(Debug)]
+struct Column<P: Predictor> {
+    predictor: P,
+    leading_zeros: u32, // leading zeros of the last xor whose leading zeros were read
+}
+
+/// MultiDecoder
+///
+/// MultiDecoder is used to decode the rows written by `MultiEncoder`. It must be created with the
+/// same number and type of predictors as the encoder.
+#[derive(Debug)]
+pub struct MultiDecoder<T: Read, P: Predictor, Q: TimestampPredictor = DeltaOfDeltaPredictor> {
+    time: u64, // current time
+    time_predictor: Q, // predicts the next time delta
+    columns: Vec<Column<P>>,
+    values: Vec<i64>, // values of the last row decoded
+
+    first: bool, // will next row be the first row decoded
+    done: bool,
+
+    index: u64, // index of the next row
+    last_time: Option<u64>, // timestamp of the last row decoded
+
+    r: T,
+}
+
+impl<T, P> MultiDecoder<T, P>
+    where T: Read,
+    P: Predictor
+{
+    /// new creates a new MultiDecoder which will read bytes from r and uses one predictor from
+    /// `predictors` for each field
+    pub fn new(r: T, predictors: Vec<P>) -> Self {
+        MultiDecoder::with_timestamp_predictor(r, predictors, DeltaOfDeltaPredictor::new())
+    }
+}
+
+impl<T, P, Q> MultiDecoder<T, P, Q>
+    where T: Read,
+    P: Predictor,
+    Q: TimestampPredictor
+{
+    /// with_timestamp_predictor creates a new MultiDecoder which will read bytes from r, uses one
+    /// predictor from `predictors` for each field and uses `q` to predict the delta between
+    /// timestamps, `q` must match the encoder's predictor
+    pub fn with_timestamp_predictor(r: T, predictors: Vec<P>, q: Q) -> Self {
+        assert!(!predictors.is_empty(), "there must be at least one field");
+
+        let values = vec![0; predictors.len()];
+        let columns = predictors.into_iter()
+            .map(|predictor| {
+                Column {
+                    predictor,
+                    leading_zeros: 0,
+                }
+            })
+            .collect();
+
+        MultiDecoder {
+            time: 0,
+            time_predictor: q,
+            columns,
+            values,
+            first: true,
+            done: false,
+            index: 0,
+            last_time: None,
+            r,
+        }
+    }
+
+    /// fields returns the number of values in each row
+    pub fn fields(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// next decodes the next row, returning its timestamp and its values, one for each field.
+    /// `Error::EndOfStream` is returned once every row has been decoded
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<(u64, &[i64]), Error> {
+        if self.done {
+            return Err(Error::EndOfStream);
+        }
+
+        match self.read_row() {
+            Ok(time) => {
+                self.index += 1;
+                self.last_time = Some(time);
+                Ok((time, &self.values))
+            }
+            Err(err) => {
+                self.done = true;
+                Err(err.at(self.position()))
+            }
+        }
+    }
+
+    // position returns the current position in the stream, to be attached to errors
+    fn position(&self) -> Position {
+        Position {
+            bit_offset: self.r.bits_read(),
+            index: self.index,
+            last_time: self.last_time,
+        }
+    }
+
+    fn read_row(&mut self) -> Result<u64, Error> {
+        if self.first {
+            self.first = false;
+            self.read_first_timestamp()?;
+            for (column, value) in self.columns.iter_mut().zip(&mut self.values) {
+                let bits = self.r.read_bits(64)?;
+                column.predictor.update(bits);
+                *value = bits as i64;
+            }
+        } else {
+            self.read_next_timestamp()?;
+            for (column, value) in self.columns.iter_mut().zip(&mut self.values) {
+                *value = read_next_value(&mut self.r, column)? as i64;
+            }
+        }
+
+        Ok(self.time)
+    }
+
+    fn read_first_timestamp(&mut self) -> Result<(), Error> {
+        self.time = self.r.read_bits(64).map_err(|_| Error::InvalidInitialTimestamp)?;
+
+        // a stream which only holds the initial timestamp is followed by END_MARKER
+        decode::read_control_bit(&mut self.r)?;
+
+        let delta = self.r.read_bits(14)?;
+        self.time_predictor.update(delta);
+        self.time += delta;
+
+        Ok(())
+    }
+
+    fn read_next_timestamp(&mut self) -> Result<(), Error> {
+        let dod = decode::read_delta_of_delta(&mut self.r)?;
+        let delta = self.time_predictor.predict_next().wrapping_add(dod);
+        self.time_predictor.update(delta);
+        self.time = self.time.wrapping_add(delta);
+
+        Ok(())
+    }
+}
+
+// read_next_value reads the next value of column
+fn read_next_value<T: Read, P: Predictor>(r: &mut T, column: &mut Column<P>) -> Result<u64, Error> {
+    let xor = decode::read_xor(r, &mut column.leading_zeros)?;
+    let value_bits = column.predictor.predict_next() ^ xor;
+    column.predictor.update(value_bits);
+    Ok(value_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use {DataPoint, Encode, StdEncoder};
+    use decode::Error;
+    use encode::multi_encoder::MultiEncoder;
+    use predictor::{FcmPredictor, Predictor, SimplePredictor};
+    use stream::{BufferedReader, BufferedWriter};
+    use super::MultiDecoder;
+
+    fn round_trip<P: Predictor>(rows: &[(u64, Vec<i64>)], predictors: fn() -> Vec<P>) {
+        let mut e = MultiEncoder::new(1482268055, BufferedWriter::new(), predictors());
+        for &(time, ref values) in rows {
+            e.encode(time, values);
+        }
+        let bytes = e.close();
+
+        let mut d = MultiDecoder::new(BufferedReader::new(bytes), predictors());
+        for &(time, ref values) in rows {
+            assert_eq!(d.next().unwrap(), (time, &values[..]));
+        }
+        assert_eq!(d.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_rows() {
+        // CPU user, system and idle percentages multiplied by 100
+        let rows: Vec<(u64, Vec<i64>)> = (0..500)
+            .map(|i| {
+                let user = 2000 + (i * 37) % 500;
+                let system = 500 + (i * 13) % 100;
+                (1482268055 + 10 + i as u64 * 10, vec![user, system, 10000 - user - system])
+            })
+            .collect();
+
+        round_trip(&rows, || vec![SimplePredictor::new(); 3]);
+        round_trip(&rows, || (0..3).map(|_| FcmPredictor::new(64)).collect());
+
+        // irregular timestamps, negative values and the extremes
+        round_trip(&[(1482268060, vec![i64::MIN, 0]),
+                     (1482268061, vec![i64::MAX, -1]),
+                     (1482268500, vec![-5, 5]),
+                     (1482300000, vec![-5, 5])],
+                   || vec![SimplePredictor::new(); 2]);
+    }
+
+    #[test]
+    fn decode_empty_stream() {
+        let e = MultiEncoder::new(1482268055, BufferedWriter::new(), vec![SimplePredictor::new()]);
+        let r = BufferedReader::new(e.close());
+        let mut d = MultiDecoder::new(r, vec![SimplePredictor::new()]);
+        assert_eq!(d.next().err().unwrap(), Error::EndOfStream);
+        assert_eq!(d.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn shared_timestamps_are_smaller() {
+        // irregular timestamps cost more than the values, which rarely change
+        let rows: Vec<(u64, [i64; 3])> = (0..100)
+            .map(|i| (1482268055 + i * 60 + i % 7, [1, 2, 3 + i as i64 / 50]))
+            .collect();
+
+        let w = BufferedWriter::new();
+        let mut e = MultiEncoder::new(1482268055, w, vec![SimplePredictor::new(); 3]);
+        for &(time, ref values) in &rows {
+            e.encode(time, values);
+        }
+        let multi = e.close().len();
+
+        let mut separate = 0;
+        for field in 0..3 {
+            let mut e = StdEncoder::new(1482268055, BufferedWriter::new(), SimplePredictor::new());
+            for &(time, ref values) in &rows {
+                e.encode(DataPoint::new(time, values[field]));
+            }
+            separate += e.close().len();
+        }
+
+        assert!(multi * 3 < separate * 2, "{} bytes vs {} bytes", multi, separate);
+    }
+}
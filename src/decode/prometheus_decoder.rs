@@ -0,0 +1,236 @@
+use {Bit, DataPoint};
+use stream::Read;
+use decode::{Decode, Error, Position, read_window_xor};
+
+/// PrometheusDecoder
+///
+/// PrometheusDecoder is used to decode `DataPoint`s from a Prometheus TSDB XOR chunk. Values are
+/// stored as float64 in the chunk and are converted with `as i64` when decoded.
+#[derive(Debug)]
+pub struct PrometheusDecoder<T: Read> {
+    time: u64, // current time
+    delta: u64, // current time delta
+    value_bits: u64, // current float value as bits
+
+    leading_zeros: u32,
+    trailing_zeros: u32,
+
+    count: Option<u16>, // number of DataPoints in the chunk, read from the header
+    read: u16, // number of DataPoints decoded so far
+    last_time: Option<u64>, // timestamp of the last DataPoint decoded
+
+    r: T,
+}
+
+impl<T> PrometheusDecoder<T>
+    where T: Read
+{
+    /// new creates a new PrometheusDecoder which will read bytes from r
+    pub fn new(r: T) -> Self {
+        PrometheusDecoder {
+            time: 0,
+            delta: 0,
+            value_bits: 0,
+            leading_zeros: 0,
+            trailing_zeros: 0,
+            count: None,
+            read: 0,
+            last_time: None,
+            r,
+        }
+    }
+
+    fn read_uvarint(&mut self) -> Result<u64, Error> {
+        let mut x = 0;
+        let mut shift = 0;
+
+        // a u64 never needs more than 10 bytes
+        for _ in 0..10 {
+            let byte = self.r.read_byte()? as u64;
+            x |= (byte & 0x7f) << shift;
+            if byte < 0x80 {
+                break;
+            }
+            shift += 7;
+        }
+
+        Ok(x)
+    }
+
+    fn read_varint(&mut self) -> Result<i64, Error> {
+        let ux = self.read_uvarint()?;
+        let mut x = (ux >> 1) as i64;
+        if ux & 1 != 0 {
+            x = !x;
+        }
+        Ok(x)
+    }
+
+    fn read_next_timestamp(&mut self) -> Result<u64, Error> {
+        let mut control_bits = 0;
+        for _ in 0..4 {
+            if self.r.read_bit()? == Bit::Zero {
+                break;
+            }
+            control_bits += 1;
+        }
+
+        let size = match control_bits {
+            0 => 0,
+            1 => 14,
+            2 => 17,
+            3 => 20,
+            4 => 64,
+            _ => unreachable!(),
+        };
+
+        let mut dod = 0;
+        if size > 0 {
+            dod = self.r.read_bits(size)?;
+
+            // need to sign extend negative numbers
+            if size < 64 && dod > (1 << (size - 1)) {
+                dod |= u64::MAX << size;
+            }
+        }
+
+        self.delta = self.delta.wrapping_add(dod);
+        self.time = self.time.wrapping_add(self.delta);
+
+        Ok(self.time)
+    }
+
+    fn read_next_value(&mut self) -> Result<u64, Error> {
+        self.value_bits ^= read_window_xor(&mut self.r,
+                                           &mut self.leading_zeros,
+                                           &mut self.trailing_zeros)?;
+
+        Ok(self.value_bits)
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            bit_offset: self.r.bits_read(),
+            index: self.read as u64,
+            last_time: self.last_time,
+        }
+    }
+
+    fn read_datapoint(&mut self) -> Result<DataPoint, Error> {
+        let count = match self.count {
+            Some(count) => count,
+            None => {
+                let count = self.r.read_bits(16)? as u16;
+                self.count = Some(count);
+                count
+            }
+        };
+
+        if self.read == count {
+            return Err(Error::EndOfStream);
+        }
+
+        match self.read {
+            0 => {
+                self.time = self.read_varint().map_err(|_| Error::InvalidInitialTimestamp)? as u64;
+                self.value_bits = self.r.read_bits(64)?;
+            }
+            1 => {
+                self.delta = self.read_uvarint()?;
+                self.time = self.time.wrapping_add(self.delta);
+                self.read_next_value()?;
+            }
+            _ => {
+                self.read_next_timestamp()?;
+                self.read_next_value()?;
+            }
+        }
+
+        self.read += 1;
+
+        Ok(DataPoint::new(self.time, f64::from_bits(self.value_bits) as i64))
+    }
+}
+
+impl<T> Decode for PrometheusDecoder<T>
+    where T: Read
+{
+    fn next(&mut self) -> Result<DataPoint, Error> {
+        match self.read_datapoint() {
+            Ok(dp) => {
+                self.last_time = Some(dp.time);
+                Ok(dp)
+            }
+            Err(err) => Err(err.at(self.position())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {DataPoint, Decode};
+    use stream::BufferedReader;
+    use decode::Error;
+    use super::PrometheusDecoder;
+
+    #[test]
+    fn create_new_decoder() {
+        let bytes = vec![0, 0];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let mut decoder = PrometheusDecoder::new(r);
+
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_datapoint() {
+        let bytes = vec![0, 1, 128, 224, 188, 239, 167, 87, 63, 240, 0, 0, 0, 0, 0, 0];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let mut decoder = PrometheusDecoder::new(r);
+
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(1500000000000, 1));
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_multiple_datapoints() {
+        let bytes = vec![0, 11, 128, 224, 188, 239, 167, 87, 63, 240, 0, 0, 0, 0, 0, 0, 152, 117,
+                         194, 95, 255, 27, 1, 201, 196, 96, 58, 0, 92, 156, 64, 160, 35, 229, 48,
+                         32, 116, 244, 180, 79, 236, 120, 0, 0, 0, 0, 47, 174, 232, 198, 22, 255,
+                         132, 14, 111, 255, 255, 255, 255, 253, 5, 44, 204, 47, 6, 32, 26, 3, 154];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let mut decoder = PrometheusDecoder::new(r);
+
+        let start = 1500000000000;
+        let expected_datapoints = vec![DataPoint::new(start, 1),
+                                       DataPoint::new(start + 15000, 2),
+                                       DataPoint::new(start + 30000, 2),
+                                       DataPoint::new(start + 45000, 3),
+                                       DataPoint::new(start + 65000, -7),
+                                       DataPoint::new(start + 125000, 1024),
+                                       DataPoint::new(start + 525000, 1024),
+                                       DataPoint::new(start + 526000, 1),
+                                       DataPoint::new(start + 100526000, 98765),
+                                       DataPoint::new(start + 100541000, 98765),
+                                       DataPoint::new(start + 100555000, 12)];
+
+        for dp in expected_datapoints {
+            assert_eq!(decoder.next().unwrap(), dp);
+        }
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_invalid_xor_window() {
+        // the second value has 31 leading zeros and 63 significant digits, 94 bits in total
+        let bytes = vec![0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0b11111111, 0b11111000];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let mut decoder = PrometheusDecoder::new(r);
+
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(0, 0));
+
+        let err = decoder.next().err().unwrap();
+        assert_eq!(*err.kind(), Error::InvalidXorWindow);
+        assert!(err.is_malformed());
+    }
+}
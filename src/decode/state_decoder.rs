@@ -0,0 +1,225 @@
+use {Bit, DataPoint};
+use stream::Read;
+use decode::{self, Decode, Error, Position};
+use predictor::{TimestampPredictor, DeltaOfDeltaPredictor};
+
+/// StateDecoder
+///
+/// StateDecoder is used to decode the runs of `DataPoint`s written by `StateEncoder`
+#[derive(Debug)]
+pub struct StateDecoder<T: Read, Q: TimestampPredictor = DeltaOfDeltaPredictor> {
+    time: u64, // current time
+    time_predictor: Q, // predicts the next time delta
+
+    value: i64, // value of the current run
+    previous: Option<i64>, // value of the run before the current one
+    remaining: u64, // number of DataPoints left in the current run
+
+    first: bool, // will next DataPoint be the first DataPoint decoded
+    done: bool,
+
+    index: u64, // index of the next DataPoint
+    last_time: Option<u64>, // timestamp of the last DataPoint decoded
+
+    r: T,
+}
+
+impl<T> StateDecoder<T>
+    where T: Read
+{
+    /// new creates a new StateDecoder which will read bytes from r
+    pub fn new(r: T) -> Self {
+        StateDecoder::with_timestamp_predictor(r, DeltaOfDeltaPredictor::new())
+    }
+}
+
+impl<T, Q> StateDecoder<T, Q>
+    where T: Read,
+    Q: TimestampPredictor
+{
+    /// with_timestamp_predictor creates a new StateDecoder which will read bytes from r and uses
+    /// `q` to predict the delta between timestamps, `q` must match the encoder's predictor
+    pub fn with_timestamp_predictor(r: T, q: Q) -> Self {
+        StateDecoder {
+            time: 0,
+            time_predictor: q,
+            value: 0,
+            previous: None,
+            remaining: 0,
+            first: true,
+            done: false,
+            index: 0,
+            last_time: None,
+            r,
+        }
+    }
+
+    // position returns the current position in the stream, to be attached to errors
+    fn position(&self) -> Position {
+        Position {
+            bit_offset: self.r.bits_read(),
+            index: self.index,
+            last_time: self.last_time,
+        }
+    }
+
+    fn read_datapoint(&mut self) -> Result<DataPoint, Error> {
+        if self.first {
+            self.time = self.r.read_bits(64).map_err(|_| Error::InvalidInitialTimestamp)?;
+        }
+
+        if self.remaining == 0 {
+            self.read_run()?;
+        }
+
+        if self.first {
+            self.first = false;
+            self.read_first_timestamp()?;
+        } else {
+            self.read_next_timestamp()?;
+        }
+        self.remaining -= 1;
+
+        Ok(DataPoint::new(self.time, self.value))
+    }
+
+    // read_run reads the value and length of the next run
+    fn read_run(&mut self) -> Result<(), Error> {
+        // a run starts with a zero control bit, otherwise the stream is followed by END_MARKER
+        decode::read_control_bit(&mut self.r)?;
+
+        let value = if self.first {
+            self.r.read_bits(64)? as i64
+        } else if self.r.read_bit()? == Bit::Zero {
+            // the value is the same as the run before the last
+            self.previous.unwrap_or(0)
+        } else {
+            let leading_zeros = self.r.read_bits(6)? as u32;
+            (self.value as u64 ^ self.r.read_bits(64 - leading_zeros)?) as i64
+        };
+
+        let size = if self.r.read_bit()? == Bit::Zero {
+            0
+        } else if self.r.read_bit()? == Bit::Zero {
+            6
+        } else {
+            12
+        };
+
+        self.remaining = self.r.read_bits(size)? + 1;
+        if !self.first {
+            self.previous = Some(self.value);
+        }
+        self.value = value;
+
+        Ok(())
+    }
+
+    fn read_first_timestamp(&mut self) -> Result<(), Error> {
+        let delta = self.r.read_bits(14)?;
+        self.time_predictor.update(delta);
+        self.time += delta;
+
+        Ok(())
+    }
+
+    fn read_next_timestamp(&mut self) -> Result<(), Error> {
+        let dod = decode::read_delta_of_delta(&mut self.r)?;
+        let delta = self.time_predictor.predict_next().wrapping_add(dod);
+        self.time_predictor.update(delta);
+        self.time = self.time.wrapping_add(delta);
+
+        Ok(())
+    }
+}
+
+impl<T, Q> Decode for StateDecoder<T, Q>
+    where T: Read,
+    Q: TimestampPredictor
+{
+    fn next(&mut self) -> Result<DataPoint, Error> {
+        if self.done {
+            return Err(Error::EndOfStream);
+        }
+
+        match self.read_datapoint() {
+            Ok(dp) => {
+                self.index += 1;
+                self.last_time = Some(dp.time);
+                Ok(dp)
+            }
+            Err(err) => {
+                self.done = true;
+                Err(err.at(self.position()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {DataPoint, Decode, Encode, StdEncoder, SimplePredictor};
+    use decode::Error;
+    use encode::state_encoder::StateEncoder;
+    use stream::{BufferedReader, BufferedWriter};
+    use super::StateDecoder;
+
+    fn round_trip(datapoints: &[DataPoint]) -> Box<[u8]> {
+        let mut e = StateEncoder::new(1482268055, BufferedWriter::new());
+        for dp in datapoints {
+            e.encode(*dp);
+        }
+        let bytes = e.close();
+
+        let mut d = StateDecoder::new(BufferedReader::new(bytes.clone()));
+        for dp in datapoints {
+            assert_eq!(d.next().unwrap(), *dp);
+        }
+        assert_eq!(d.next().err().unwrap(), Error::EndOfStream);
+        assert_eq!(d.next().err().unwrap(), Error::EndOfStream);
+
+        bytes
+    }
+
+    #[test]
+    fn decode_boolean() {
+        // a service which is up, apart from short outages
+        let datapoints: Vec<DataPoint> = (0..10000)
+            .map(|i| DataPoint::new(1482268065 + i * 10, if i % 1000 < 990 { 1 } else { 0 }))
+            .collect();
+        let bytes = round_trip(&datapoints);
+
+        let mut e = StdEncoder::new(1482268055, BufferedWriter::new(), SimplePredictor::new());
+        for dp in &datapoints {
+            e.encode(*dp);
+        }
+        let std = e.close().len();
+
+        // each regular timestamp still costs a bit, StdEncoder spends another on each value
+        assert!(bytes.len() * 3 < std * 2, "{} bytes vs {} bytes", bytes.len(), std);
+    }
+
+    #[test]
+    fn decode_states() {
+        // status codes with single DataPoint runs, runs of every length and irregular timestamps
+        let mut time = 1482268060;
+        let mut datapoints = Vec::new();
+        let runs = [(200, 1), (503, 1), (200, 2), (404, 64), (200, 65), (-1, 4096),
+                    (i64::MIN, 4097), (i64::MAX, 3), (200, 1), (503, 1), (503, 1)];
+        for (run, &(value, len)) in runs.iter().enumerate() {
+            for i in 0..len {
+                time += 10 + (run as u64 * 7 + i) % 13;
+                datapoints.push(DataPoint::new(time, value));
+            }
+        }
+        round_trip(&datapoints);
+
+        round_trip(&[DataPoint::new(1482268060, 0)]);
+        round_trip(&[DataPoint::new(1482268060, 1), DataPoint::new(1482300000, 1)]);
+    }
+
+    #[test]
+    fn decode_empty_stream() {
+        round_trip(&[]);
+    }
+}
@@ -1,18 +1,19 @@
 use std::mem;
 
 use {Bit, DataPoint};
-use stream::Read;
-use decode::{Decode, Error};
-use encode::std_encoder::{END_MARKER, END_MARKER_LEN};
-use predictor::Predictor;
+use stream::{self, BufferedReader, Read};
+use checksum::{self, Crc32c};
+use decode::{self, Decode, Error, Position};
+use encode::std_encoder::{SYNC_ESCAPE, SYNC_ESCAPE_LEN, SYNC_MAGIC, Framing};
+use predictor::{Predictor, TimestampPredictor, DeltaOfDeltaPredictor};
 
 /// StdDecoder
 ///
 /// StdDecoder is used to decode `DataPoint`s
 #[derive(Debug)]
-pub struct StdDecoder<T: Read, P: Predictor> {
+pub struct StdDecoder<T: Read, P: Predictor, Q: TimestampPredictor = DeltaOfDeltaPredictor> {
     time: u64, // current time
-    delta: u64, // current time delta
+    time_predictor: Q, // predicts the next time delta
     predictor: P,
 
     leading_zeros: u32, // leading zeros
@@ -21,6 +22,16 @@ pub struct StdDecoder<T: Read, P: Predictor> {
     first: bool, // will next DataPoint be the first DataPoint decoded
     done: bool,
 
+    framing: Framing,
+    remaining: u64, // number of DataPoints left in a counted stream
+    len: u64, // length in bits of a counted stream
+    checksum: Option<Crc32c>, // checksum of the bytes read so far
+
+    recover: bool, // skip to the next sync point after an error
+    resynced: bool, // is the reader positioned just after the SYNC_MAGIC of a sync point
+    index: u64, // index of the next DataPoint
+    last_time: Option<u64>, // timestamp of the last DataPoint decoded
+
     r: T,
 }
 
@@ -29,21 +40,199 @@ impl<T, P> StdDecoder<T, P>
 {
     /// new creates a new StdDecoder which will read bytes from r
     pub fn new(r: T, p: P) -> Self {
+        StdDecoder::with_timestamp_predictor(r, p, DeltaOfDeltaPredictor::new())
+    }
+}
+
+impl<T, P, Q> StdDecoder<T, P, Q>
+    where T: Read, P: Predictor, Q: TimestampPredictor
+{
+    /// with_timestamp_predictor creates a new StdDecoder which will read bytes from r and uses
+    /// `q` to predict the delta between timestamps, `q` must match the encoder's predictor
+    pub fn with_timestamp_predictor(r: T, p: P, q: Q) -> Self {
         StdDecoder {
             time: 0,
-            delta: 0,
+            time_predictor: q,
             predictor: p,
             leading_zeros: 0,
             //trailing_zeros: 0,
             first: true,
             done: false,
+            framing: Framing::EndMarker,
+            remaining: 0,
+            len: 0,
+            checksum: None,
+            recover: false,
+            resynced: false,
+            index: 0,
+            last_time: None,
             r: r,
         }
     }
 
+    /// framing sets how the end of the stream is found, it must match the framing used by the
+    /// encoder and be set before any `DataPoint`s are decoded
+    pub fn framing(mut self, framing: Framing) -> Self {
+        assert!(self.first, "framing must be set before decoding any DataPoints");
+        self.framing = framing;
+        self
+    }
+
+    /// checksum sets whether the stream is followed by a CRC32C trailer, it must be set before
+    /// any `DataPoint`s are decoded. The trailer is only checked once `next` reaches the end of
+    /// the stream, so the `DataPoint`s returned before then may come from a corrupt stream. When
+    /// the whole stream is held by a `BufferedReader` use `verify` to check it up front
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        assert!(self.first, "checksum must be set before decoding any DataPoints");
+        self.checksum = if checksum { Some(Crc32c::new()) } else { None };
+        self
+    }
+
+    /// recover sets whether the decoder should skip to the next sync point when it fails to
+    /// decode a `DataPoint`. When it does, `next` returns `Error::PointsLost` with the range of
+    /// `DataPoint`s that were skipped and then carries on from the sync point. If there are no
+    /// more sync points the original error is returned. A checksum can no longer be verified once
+    /// part of the stream has been skipped
+    pub fn recover(mut self, recover: bool) -> Self {
+        self.recover = recover;
+        self
+    }
+
+    // position returns the current position in the stream, to be attached to errors
+    fn position(&self) -> Position {
+        Position {
+            bit_offset: self.r.bits_read(),
+            index: self.index,
+            last_time: self.last_time,
+        }
+    }
+
+    // all reads go through reader so every bit is added to the checksum
+    fn reader<'a>(&'a mut self) -> Checksummed<'a, T> {
+        Checksummed {
+            r: &mut self.r,
+            checksum: &mut self.checksum,
+        }
+    }
+
+    fn read_bit(&mut self) -> Result<Bit, stream::Error> {
+        self.reader().read_bit()
+    }
+
+    fn read_bits(&mut self, num: u32) -> Result<u64, stream::Error> {
+        self.reader().read_bits(num)
+    }
+
+    // finish_counted ends a counted stream, checking that exactly the expected number of bits
+    // were read
+    fn finish_counted(&mut self) -> Error {
+        if self.r.bits_read() == self.len {
+            Error::EndOfStream
+        } else {
+            Error::InvalidStreamLength
+        }
+    }
+
+    // verify_checksum checks the checksum trailer once the end of the stream has been reached
+    fn verify_checksum(&mut self) -> Error {
+        let pending = match self.checksum {
+            Some(ref checksum) => checksum.pending_bits(),
+            None => return Error::EndOfStream,
+        };
+
+        // the checksum covers the padding up to the next byte boundary
+        if let Err(err) = self.read_bits(pending) {
+            return Error::Stream(err);
+        }
+
+        let expected = self.checksum.as_ref().map_or(0, |checksum| checksum.finish());
+        match self.r.read_bits(32) {
+            Ok(actual) if actual == expected as u64 => Error::EndOfStream,
+            Ok(_) => Error::ChecksumMismatch,
+            Err(err) => Error::Stream(err),
+        }
+    }
+
+    // read_sync_point reads a sync point and resets both predictors, if the decoder has just
+    // resynced the reader is already past the SYNC_ESCAPE, its padding and SYNC_MAGIC
+    fn read_sync_point(&mut self) -> Result<(u64, u64), Error> {
+        if self.resynced {
+            self.resynced = false;
+        } else {
+            self.read_bits(SYNC_ESCAPE_LEN)?;
+            let padding = (8 - self.r.bits_read() % 8) % 8;
+            self.read_bits(padding as u32)?;
+            if self.read_bits(32)? != SYNC_MAGIC {
+                return Err(Error::InvalidSyncPoint);
+            }
+        }
+
+        if self.read_bits(64)? != self.index {
+            return Err(Error::InvalidSyncPoint);
+        }
+
+        self.time = self.read_bits(64)?;
+        let value_bits = self.read_bits(64)?;
+
+        self.time_predictor.reset();
+        self.predictor.reset();
+        self.predictor.update(value_bits);
+        self.leading_zeros = 0;
+
+        Ok((self.time, value_bits))
+    }
+
+    // resync scans forward from the byte after the current position to the next sync point, it
+    // returns the range of DataPoints that were skipped or `err` if no sync point is found
+    fn resync(&mut self, err: Error) -> Error {
+        // the skipped bytes are known to be damaged so the checksum is meaningless
+        self.checksum = None;
+        self.first = false;
+
+        let padding = (8 - self.r.bits_read() % 8) % 8;
+        if self.r.read_bits(padding as u32).is_err() {
+            self.done = true;
+            return err;
+        }
+
+        // a sync point for an earlier DataPoint, or one beyond the end of a counted stream,
+        // must be a false match on damaged bytes
+        let last = if self.framing == Framing::Counted {
+            self.index + self.remaining
+        } else {
+            u64::MAX
+        };
+
+        while let Ok(bits) = self.r.peak_bits(32) {
+            if bits != SYNC_MAGIC {
+                if self.r.read_byte().is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            // no suffix of SYNC_MAGIC is also a prefix of it, so if the index which follows does
+            // not match scanning can carry on after the SYNC_MAGIC
+            let _ = self.r.read_bits(32);
+            match self.r.peak_bits(64) {
+                Ok(index) if index >= self.index && index < last => {
+                    let start = self.index;
+                    self.remaining = last.wrapping_sub(index);
+                    self.index = index;
+                    self.resynced = true;
+                    return Error::PointsLost(start, index);
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+
+        self.done = true;
+        err
+    }
+
     fn read_initial_timestamp(&mut self) -> Result<u64, Error> {
-        self.r
-            .read_bits(64)
+        self.read_bits(64)
             .map_err(|_| Error::InvalidInitialTimestamp)
             .map(|time| {
                 self.time = time;
@@ -54,26 +243,25 @@ impl<T, P> StdDecoder<T, P>
     fn read_first_timestamp(&mut self) -> Result<u64, Error> {
         self.read_initial_timestamp()?;
 
-        // sanity check to confirm that the stream contains more than just the initial timestamp
-        let control_bit = self.r.peak_bits(1)?;
-        if control_bit == 1 {
-            return self.r
-                .read_bits(END_MARKER_LEN)
-                .map_err(|err| Error::Stream(err))
-                .and_then(|marker| if marker == END_MARKER {
-                    Err(Error::EndOfStream)
-                } else {
-                    Err(Error::InvalidEndOfStream)
-                });
+        if self.framing == Framing::Counted {
+            self.remaining = self.read_bits(64)?;
+            self.len = self.read_bits(64)?;
+            if self.remaining == 0 {
+                return Err(self.finish_counted());
+            }
         }
 
-        // stream contains datapoints so we can throw away the control bit
-        self.r.read_bit()?;
+        // sanity check to confirm that the stream contains more than just the initial timestamp,
+        // a counted stream which gets this far holds at least one DataPoint so it is not checked
+        if self.framing == Framing::EndMarker {
+            decode::read_control_bit(&mut self.reader())?;
+        } else {
+            self.read_bit()?;
+        }
 
-        self.r
-            .read_bits(14)
+        self.read_bits(14)
             .map(|delta| {
-                self.delta = delta;
+                self.time_predictor.update(delta);
                 self.time += delta;
             })?;
 
@@ -81,56 +269,18 @@ impl<T, P> StdDecoder<T, P>
     }
 
     fn read_next_timestamp(&mut self) -> Result<u64, Error> {
-        let mut control_bits = 0;
-        for _ in 0..4 {
-            let bit = self.r.read_bit()?;
-
-            if bit == Bit::One {
-                control_bits += 1;
-            } else {
-                break;
-            }
-        }
-
-        let size = match control_bits {
-            0 => {
-                self.time += self.delta;
-                return Ok(self.time);
-            }
-            1 => 7,
-            2 => 9,
-            3 => 12,
-            4 => {
-                return self.r
-                    .read_bits(32)
-                    .map_err(|err| Error::Stream(err))
-                    .and_then(|dod| if dod == 0 {
-                        Err(Error::EndOfStream)
-                    } else {
-                        Ok(dod)
-                    });
-            }
-            _ => unreachable!(),
-        };
-
-        let mut dod = self.r.read_bits(size)?;
-
-        // need to sign extend negative numbers
-        if dod > (1 << (size - 1)) {
-            let mask = u64::max_value() << size;
-            dod = dod | mask;
-        }
+        let dod = decode::read_delta_of_delta(&mut self.reader())?;
 
         // by performing a wrapping_add we can ensure that negative numbers will be handled correctly
-        self.delta = self.delta.wrapping_add(dod);
-        self.time = self.time.wrapping_add(self.delta);
+        let delta = self.time_predictor.predict_next().wrapping_add(dod);
+        self.time_predictor.update(delta);
+        self.time = self.time.wrapping_add(delta);
 
         Ok(self.time)
     }
 
     fn read_first_value(&mut self) -> Result<u64, Error> {
-        self.r
-            .read_bits(64)
+        self.read_bits(64)
             .map_err(|err| Error::Stream(err))
             .map(|bits| {
                 self.predictor.update(bits);
@@ -140,42 +290,18 @@ impl<T, P> StdDecoder<T, P>
     }
 
     fn read_next_value(&mut self) -> Result<u64, Error> {
-        let contol_bit = self.r.read_bit()?;
-        let predicted_value = self.predictor.predict_next();
-
-        if contol_bit == Bit::Zero {
-            println!("<- Bit::Zero = {}", predicted_value);
-            return Ok(predicted_value);
-        }
-
-        let zeros_bit = self.r.read_bit()?;
+        let mut leading_zeros = self.leading_zeros;
+        let xor = decode::read_xor(&mut self.reader(), &mut leading_zeros)?;
+        self.leading_zeros = leading_zeros;
 
-        if zeros_bit == Bit::One {
-            self.leading_zeros = self.r.read_bits(6).map(|n| n as u32)?;
-            //let significant_digits = self.r.read_bits(6).map(|n| (n + 1) as u32)?;
-            println!("<- significant_digits changed = 64 - {} = {}", self.leading_zeros, 64 - self.leading_zeros);
-            //self.trailing_zeros = 64 - self.leading_zeros - significant_digits;
-        }
-
-        let size = 64 - self.leading_zeros;// - self.trailing_zeros;
-        self.r
-            .read_bits(size)
-            .map_err(|err| Error::Stream(err))
-            .map(|bits| {
-                let value_bits = predicted_value ^ (bits);// << self.trailing_zeros);
-                println!("<- {} = {} ^ {}", value_bits, predicted_value, bits);
-                self.predictor.update(value_bits);
-                value_bits
-            })
+        let value_bits = self.predictor.predict_next() ^ xor;
+        self.predictor.update(value_bits);
+        Ok(value_bits)
     }
-}
 
-impl<T, P> Decode for StdDecoder<T, P>
-    where T: Read, P: Predictor
-{
-    fn next(&mut self) -> Result<DataPoint, Error> {
-        if self.done {
-            return Err(Error::EndOfStream);
+    fn read_datapoint(&mut self) -> Result<DataPoint, Error> {
+        if !self.first && self.framing == Framing::Counted && self.remaining == 0 {
+            return Err(self.finish_counted());
         }
 
         let time;
@@ -183,38 +309,125 @@ impl<T, P> Decode for StdDecoder<T, P>
 
         if self.first {
             self.first = false;
-            time = self.read_first_timestamp()
-                .map_err(|err| {
-                    if err == Error::EndOfStream {
-                        self.done = true;
-                    }
-                    err
-                })?;;
+            time = self.read_first_timestamp()?;
             value_bits = self.read_first_value()?;
+        } else if self.resynced || self.r.peak_bits(SYNC_ESCAPE_LEN).ok() == Some(SYNC_ESCAPE) {
+            let (sync_time, sync_value_bits) = self.read_sync_point()?;
+            time = sync_time;
+            value_bits = sync_value_bits;
         } else {
-            time = self.read_next_timestamp()
-                .map_err(|err| {
-                    if err == Error::EndOfStream {
-                        self.done = true;
-                    }
-                    err
-                })?;;
+            time = self.read_next_timestamp()?;
             value_bits = self.read_next_value()?;
         }
 
+        if self.framing == Framing::Counted {
+            self.remaining -= 1;
+            if self.r.bits_read() > self.len {
+                return Err(Error::InvalidStreamLength);
+            }
+        }
+
         let value = unsafe { mem::transmute::<u64, i64>(value_bits) };
 
         Ok(DataPoint::new(time, value))
     }
 }
 
+impl<P, Q> StdDecoder<BufferedReader, P, Q>
+    where P: Predictor, Q: TimestampPredictor
+{
+    /// verify checks the CRC32C trailer against the whole buffer before any `DataPoint`s are
+    /// decoded, returning `Error::ChecksumMismatch` if it does not match. It does nothing if the
+    /// stream has no checksum
+    pub fn verify(&self) -> Result<(), Error> {
+        if self.checksum.is_some() && !checksum::verify(self.r.bytes()) {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(())
+    }
+}
+
+// Checksummed reads from r, adding every bit read to checksum if there is one
+struct Checksummed<'a, T: 'a> {
+    r: &'a mut T,
+    checksum: &'a mut Option<Crc32c>,
+}
+
+impl<'a, T> Read for Checksummed<'a, T>
+    where T: Read
+{
+    fn read_bit(&mut self) -> Result<Bit, stream::Error> {
+        let bit = self.r.read_bit()?;
+        if let Some(ref mut checksum) = *self.checksum {
+            checksum.update_bits(if bit == Bit::One { 1 } else { 0 }, 1);
+        }
+        Ok(bit)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, stream::Error> {
+        self.read_bits(8).map(|byte| byte as u8)
+    }
+
+    fn read_bits(&mut self, num: u32) -> Result<u64, stream::Error> {
+        let bits = self.r.read_bits(num)?;
+        if let Some(ref mut checksum) = *self.checksum {
+            checksum.update_bits(bits, num);
+        }
+        Ok(bits)
+    }
+
+    fn peak_bits(&mut self, num: u32) -> Result<u64, stream::Error> {
+        self.r.peak_bits(num)
+    }
+
+    fn bits_read(&self) -> u64 {
+        self.r.bits_read()
+    }
+}
+
+impl<T, P, Q> Decode for StdDecoder<T, P, Q>
+    where T: Read, P: Predictor, Q: TimestampPredictor
+{
+    fn next(&mut self) -> Result<DataPoint, Error> {
+        if self.done {
+            return Err(Error::EndOfStream);
+        }
+
+        match self.read_datapoint() {
+            Ok(dp) => {
+                self.index += 1;
+                self.last_time = Some(dp.time);
+                Ok(dp)
+            }
+            Err(Error::EndOfStream) => {
+                self.done = true;
+                let err = self.verify_checksum();
+                Err(err.at(self.position()))
+            }
+            Err(Error::InvalidStreamLength) => {
+                self.done = true;
+                Err(Error::InvalidStreamLength.at(self.position()))
+            }
+            Err(err) => {
+                let err = err.at(self.position());
+                if self.recover {
+                    return Err(self.resync(err));
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use {DataPoint, Decode};
     use stream::BufferedReader;
-    use decode::Error;
+    use decode::{Error, Position};
     use super::StdDecoder;
+    use encode::std_encoder::Framing;
     use predictor::SimplePredictor;
+    use checksum;
 
     #[test]
     fn create_new_decoder() {
@@ -263,4 +476,113 @@ mod tests {
         assert_eq!(decoder.next().unwrap(), fifth_expected_datapoint);
         assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn decode_counted_datapoint() {
+        let bytes = vec![0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+                         1, 15, 0, 20, 0, 0, 0, 0, 0, 0, 0, 248];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p).framing(Framing::Counted);
+
+        let expected_datapoint = DataPoint::new(1482268055 + 10, 124);
+
+        assert_eq!(decoder.next().unwrap(), expected_datapoint);
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_counted_empty_stream() {
+        let bytes = vec![0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                         0, 192];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p).framing(Framing::Counted);
+
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_counted_invalid_length() {
+        // the header claims the stream is one bit longer than it is
+        let bytes = vec![0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+                         1, 16, 0, 20, 0, 0, 0, 0, 0, 0, 0, 248];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p).framing(Framing::Counted);
+
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(1482268055 + 10, 124));
+        assert_eq!(*decoder.next().err().unwrap().kind(), Error::InvalidStreamLength);
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_checksummed_datapoint() {
+        let bytes = vec![0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+                         1, 15, 0, 20, 0, 0, 0, 0, 0, 0, 0, 248];
+        let bytes = checksum::append_checksum(bytes.into_boxed_slice());
+        let r = BufferedReader::new(bytes);
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p).framing(Framing::Counted).checksum(true);
+
+        assert_eq!(decoder.verify(), Ok(()));
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(1482268055 + 10, 124));
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_checksum_mismatch() {
+        let bytes = vec![0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+                         1, 15, 0, 20, 0, 0, 0, 0, 0, 0, 0, 248];
+        let mut bytes = checksum::append_checksum(bytes.into_boxed_slice()).into_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p).framing(Framing::Counted).checksum(true);
+
+        assert_eq!(decoder.verify(), Err(Error::ChecksumMismatch));
+        assert_eq!(decoder.next().unwrap(), DataPoint::new(1482268055 + 10, 124));
+        assert_eq!(*decoder.next().err().unwrap().kind(), Error::ChecksumMismatch);
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_truncated_stream() {
+        // the counted stream from decode_counted_datapoint with its last six bytes missing
+        let bytes = vec![0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+                         1, 15, 0, 20, 0, 0];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p).framing(Framing::Counted);
+
+        let err = decoder.next().err().unwrap();
+        assert!(err.is_truncated());
+        assert!(!err.is_malformed());
+        assert_eq!(err.position(),
+                   Some(Position {
+                       bit_offset: 223,
+                       index: 0,
+                       last_time: None,
+                   }));
+    }
+
+    #[test]
+    fn decode_malformed_end_marker() {
+        let bytes = vec![0, 0, 0, 0, 88, 89, 157, 151, 255, 255, 255, 255, 255];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p);
+
+        let err = decoder.next().err().unwrap();
+        assert!(err.is_malformed());
+        assert!(!err.is_truncated());
+        assert_eq!(err,
+                   Error::At(Position {
+                                 bit_offset: 100,
+                                 index: 0,
+                                 last_time: None,
+                             },
+                             Box::new(Error::InvalidEndOfStream)));
+    }
+}
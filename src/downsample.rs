@@ -0,0 +1,262 @@
+use {DataPoint, Decode, Encode};
+use decode;
+use encode::std_encoder::StdEncoder;
+use predictor::SimplePredictor;
+use stream::BufferedWriter;
+
+/// Aggregate
+///
+/// Aggregate is a summary of the `DataPoint`s in a bucket.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Aggregate {
+    Min,
+    Max,
+    Sum,
+    Count,
+    First,
+    Last,
+}
+
+impl Aggregate {
+    /// ALL holds every Aggregate
+    pub const ALL: [Aggregate; 6] = [Aggregate::Min,
+                                     Aggregate::Max,
+                                     Aggregate::Sum,
+                                     Aggregate::Count,
+                                     Aggregate::First,
+                                     Aggregate::Last];
+}
+
+/// Bucket
+///
+/// Bucket summarises the `DataPoint`s whose timestamps fall within a fixed interval of time.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bucket {
+    start: u64, // start of the interval, inclusive
+    min: i64,
+    max: i64,
+    sum: i64,
+    count: u64,
+    first: DataPoint,
+    last: DataPoint,
+}
+
+impl Bucket {
+    fn new(start: u64, dp: DataPoint) -> Self {
+        Bucket {
+            start,
+            min: dp.value,
+            max: dp.value,
+            sum: dp.value,
+            count: 1,
+            first: dp,
+            last: dp,
+        }
+    }
+
+    fn add(&mut self, dp: DataPoint) {
+        self.min = self.min.min(dp.value);
+        self.max = self.max.max(dp.value);
+        self.sum = self.sum.saturating_add(dp.value);
+        self.count += 1;
+        if dp.time < self.first.time {
+            self.first = dp;
+        }
+        if dp.time >= self.last.time {
+            self.last = dp;
+        }
+    }
+
+    /// merge combines `other`, which must cover the same interval, into the bucket. This joins the
+    /// two halves of a bucket which was split across the edges of two streams
+    pub fn merge(&mut self, other: &Bucket) {
+        assert_eq!(self.start, other.start, "buckets must cover the same interval");
+
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum = self.sum.saturating_add(other.sum);
+        self.count += other.count;
+        if other.first.time < self.first.time {
+            self.first = other.first;
+        }
+        if other.last.time >= self.last.time {
+            self.last = other.last;
+        }
+    }
+
+    /// start returns the start of the interval covered by the bucket, inclusive
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// len returns the number of `DataPoint`s in the bucket
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// is_empty returns true if the bucket contains no `DataPoint`s, which never happens since
+    /// buckets are only created for `DataPoint`s
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// value returns `aggregate` of the `DataPoint`s in the bucket. Sum saturates rather than
+    /// overflowing
+    pub fn value(&self, aggregate: Aggregate) -> i64 {
+        match aggregate {
+            Aggregate::Min => self.min,
+            Aggregate::Max => self.max,
+            Aggregate::Sum => self.sum,
+            Aggregate::Count => self.count as i64,
+            Aggregate::First => self.first.value,
+            Aggregate::Last => self.last.value,
+        }
+    }
+}
+
+/// Downsampler
+///
+/// Downsampler buckets `DataPoint`s by a fixed interval aligned to the epoch, so that a bucket
+/// starts at every multiple of the interval, and writes each `Aggregate` of the buckets as its
+/// own stream. Intervals without any `DataPoint`s have no bucket and are left out of the streams
+/// rather than being filled with zeros. The buckets at the edges of a stream may only hold part of
+/// their interval, feeding the adjacent stream to the same Downsampler completes them.
+#[derive(Debug, Clone)]
+pub struct Downsampler {
+    interval: u64,
+    buckets: Vec<Bucket>, // ordered by start
+}
+
+impl Downsampler {
+    /// new creates a new Downsampler whose buckets are `interval` long, it must be in the same
+    /// unit as the timestamps of the `DataPoint`s added
+    pub fn new(interval: u64) -> Self {
+        assert!(interval > 0, "interval must be greater than zero");
+
+        Downsampler {
+            interval,
+            buckets: Vec::new(),
+        }
+    }
+
+    /// interval returns the length of the interval covered by each bucket
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// add adds `dp` to the bucket covering its timestamp. `DataPoint`s are cheapest to add in
+    /// order of time but may be added in any order
+    pub fn add(&mut self, dp: DataPoint) {
+        let start = dp.time - dp.time % self.interval;
+
+        match self.buckets.last_mut() {
+            Some(last) if last.start == start => last.add(dp),
+            Some(last) if last.start > start => {
+                match self.buckets.binary_search_by_key(&start, |bucket| bucket.start) {
+                    Ok(i) => self.buckets[i].add(dp),
+                    Err(i) => self.buckets.insert(i, Bucket::new(start, dp)),
+                }
+            }
+            _ => self.buckets.push(Bucket::new(start, dp)),
+        }
+    }
+
+    /// consume adds every `DataPoint` read from `decoder` until the end of its stream
+    pub fn consume<D: Decode>(&mut self, decoder: &mut D) -> Result<(), decode::Error> {
+        decode::for_each(decoder, |dp| {
+            self.add(dp);
+            true
+        })
+    }
+
+    /// buckets returns the buckets holding at least one `DataPoint`, ordered by time
+    pub fn buckets(&self) -> &[Bucket] {
+        &self.buckets
+    }
+
+    /// encode writes `aggregate` of each bucket to a stream with `StdEncoder`, as a `DataPoint`
+    /// whose timestamp is the start of the bucket
+    pub fn encode(&self, aggregate: Aggregate) -> Box<[u8]> {
+        let start = self.buckets.first().map_or(0, |bucket| bucket.start);
+        let mut encoder = StdEncoder::new(start, BufferedWriter::new(), SimplePredictor::new());
+        for bucket in &self.buckets {
+            encoder.encode(DataPoint::new(bucket.start, bucket.value(aggregate)));
+        }
+
+        encoder.close()
+    }
+
+    /// encode_all writes every `Aggregate` of the buckets to its own stream
+    pub fn encode_all(&self) -> Vec<(Aggregate, Box<[u8]>)> {
+        Aggregate::ALL
+            .iter()
+            .map(|&aggregate| (aggregate, self.encode(aggregate)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use test_util::{decode, decoder};
+    use super::{Aggregate, Downsampler};
+
+    #[test]
+    fn downsample_stream() {
+        // one minute buckets starting at 1482892200, nothing arrives during the third minute
+        let mut decoder = decoder(&[DataPoint::new(1482892230, 5),
+                                    DataPoint::new(1482892250, -2),
+                                    DataPoint::new(1482892260, 7),
+                                    DataPoint::new(1482892270, 3),
+                                    DataPoint::new(1482892319, 4),
+                                    DataPoint::new(1482892380, 9),
+                                    DataPoint::new(1482892390, 1)]);
+
+        let mut downsampler = Downsampler::new(60);
+        downsampler.consume(&mut decoder).unwrap();
+
+        let starts: Vec<u64> = downsampler.buckets().iter().map(|bucket| bucket.start()).collect();
+        assert_eq!(starts, vec![1482892200, 1482892260, 1482892380]);
+
+        let streams = downsampler.encode_all();
+        assert_eq!(streams.len(), 6);
+
+        let expected = [(Aggregate::Min, [-2, 3, 1]),
+                        (Aggregate::Max, [5, 7, 9]),
+                        (Aggregate::Sum, [3, 14, 10]),
+                        (Aggregate::Count, [2, 3, 2]),
+                        (Aggregate::First, [5, 7, 9]),
+                        (Aggregate::Last, [-2, 4, 1])];
+        for (i, &(aggregate, values)) in expected.iter().enumerate() {
+            assert_eq!(streams[i].0, aggregate);
+
+            let expected: Vec<DataPoint> = starts.iter()
+                .zip(&values)
+                .map(|(&start, &value)| DataPoint::new(start, value))
+                .collect();
+            assert_eq!(decode(streams[i].1.clone()), expected, "{:?}", aggregate);
+        }
+    }
+
+    #[test]
+    fn downsample_partial_buckets() {
+        // a block boundary at 1482892230 splits the bucket starting at 1482892200
+        let mut first = decoder(&[DataPoint::new(1482892200, 1), DataPoint::new(1482892220, 2)]);
+        let mut second = decoder(&[DataPoint::new(1482892230, 3), DataPoint::new(1482892250, 4)]);
+
+        let mut downsampler = Downsampler::new(60);
+        downsampler.consume(&mut second).unwrap();
+        assert_eq!(downsampler.buckets()[0].len(), 2);
+        assert_eq!(downsampler.buckets()[0].value(Aggregate::First), 3);
+
+        // the earlier block completes the bucket even when it arrives later
+        downsampler.consume(&mut first).unwrap();
+
+        let buckets = downsampler.buckets();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].len(), 4);
+        assert_eq!(buckets[0].value(Aggregate::Sum), 10);
+        assert_eq!(buckets[0].value(Aggregate::First), 1);
+        assert_eq!(buckets[0].value(Aggregate::Last), 4);
+    }
+}
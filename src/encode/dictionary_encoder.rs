@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::mem;
+
+use Bit;
+use stream::Write;
+use encode;
+use encode::std_encoder::{END_MARKER, END_MARKER_LEN};
+use predictor::{TimestampPredictor, DeltaOfDeltaPredictor};
+
+/// MAX_LABEL_LEN is the length, in bytes, of the longest label which can be encoded
+pub const MAX_LABEL_LEN: usize = 65535;
+
+/// DictionaryEncoder
+///
+/// DictionaryEncoder encodes series whose values are short strings, such as version labels or the
+/// name of the current leader. Each distinct label is stored once, in a dictionary at the start
+/// of the block, and each point stores the index of its label in the dictionary. Timestamps are
+/// encoded in the same way as `StdEncoder`, and a label which is the same as the previous one
+/// costs a single bit. Since the dictionary is only complete once every point has been added, the
+/// points are held until `close` writes the block. The stream is terminated by END_MARKER.
+#[derive(Debug, Clone)]
+pub struct DictionaryEncoder<T: Write, Q: TimestampPredictor = DeltaOfDeltaPredictor> {
+    time: u64, // current time
+    time_predictor: Q, // predicts the next time delta
+
+    labels: Vec<String>, // dictionary of labels in the order they were first seen
+    indices: HashMap<String, u64>, // index of each label in the dictionary
+    points: Vec<(u64, u64)>, // timestamp and label index of each point
+
+    w: T,
+}
+
+impl<T> DictionaryEncoder<T>
+    where T: Write
+{
+    /// new creates a new DictionaryEncoder whose starting timestamp is `start` and writes its
+    /// encoded bytes to `w`
+    pub fn new(start: u64, w: T) -> Self {
+        DictionaryEncoder::with_timestamp_predictor(start, w, DeltaOfDeltaPredictor::new())
+    }
+}
+
+impl<T, Q> DictionaryEncoder<T, Q>
+    where T: Write,
+    Q: TimestampPredictor
+{
+    /// with_timestamp_predictor creates a new DictionaryEncoder whose starting timestamp is
+    /// `start`, which writes its encoded bytes to `w` and uses `q` to predict the delta between
+    /// timestamps
+    pub fn with_timestamp_predictor(start: u64, w: T, q: Q) -> Self {
+        let mut e = DictionaryEncoder {
+            time: start,
+            time_predictor: q,
+            labels: Vec::new(),
+            indices: HashMap::new(),
+            points: Vec::new(),
+            w,
+        };
+
+        // write timestamp header
+        e.w.write_bits(start, 64);
+
+        e
+    }
+
+    /// encode adds a point whose value is `label` at `time`, `label` must be at most
+    /// MAX_LABEL_LEN bytes long
+    pub fn encode(&mut self, time: u64, label: &str) {
+        assert!(label.len() <= MAX_LABEL_LEN, "labels must be at most {} bytes", MAX_LABEL_LEN);
+
+        let index = match self.indices.get(label) {
+            Some(&index) => index,
+            None => {
+                let index = self.labels.len() as u64;
+                self.labels.push(label.to_string());
+                self.indices.insert(label.to_string(), index);
+                index
+            }
+        };
+
+        self.points.push((time, index));
+    }
+
+    /// labels returns the distinct labels added so far, in the order they were first seen
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// close writes the dictionary, the points and END_MARKER and returns the encoded bytes
+    pub fn close(mut self) -> Box<[u8]> {
+        self.write_dictionary();
+
+        let width = index_width(self.labels.len());
+        let points = mem::take(&mut self.points);
+        let mut previous = None;
+        for (time, index) in points {
+            match previous {
+                None => {
+                    self.write_first_timestamp(time);
+                    self.w.write_bits(index, width);
+                }
+                Some(previous) => {
+                    self.write_next_timestamp(time);
+                    if index == previous {
+                        // the same label as the previous point so just store a single zero bit
+                        self.w.write_bit(Bit::Zero);
+                    } else {
+                        self.w.write_bit(Bit::One);
+                        self.w.write_bits(index, width);
+                    }
+                }
+            }
+            previous = Some(index);
+        }
+
+        self.w.write_bits(END_MARKER, END_MARKER_LEN);
+        self.w.close()
+    }
+
+    fn write_dictionary(&mut self) {
+        self.w.write_bits(self.labels.len() as u64, 32);
+        for label in &self.labels {
+            self.w.write_bits(label.len() as u64, 16);
+            for &byte in label.as_bytes() {
+                self.w.write_bits(byte as u64, 8);
+            }
+        }
+    }
+
+    fn write_first_timestamp(&mut self, time: u64) {
+        let delta = time - self.time;
+        self.time = time;
+        self.time_predictor.update(delta);
+
+        // write one control bit so we can distinguish a stream which contains only an initial
+        // timestamp and the dictionary, this assumes the first bit of the END_MARKER is 1
+        self.w.write_bit(Bit::Zero);
+
+        // store the first delta with 14 bits as StdEncoder does
+        self.w.write_bits(delta, 14);
+    }
+
+    fn write_next_timestamp(&mut self, time: u64) {
+        let delta = time - self.time;
+        let dod = delta.wrapping_sub(self.time_predictor.predict_next()) as i32;
+
+        // store the delta of delta using variable length encoding
+        encode::write_delta_of_delta(&mut self.w, dod);
+
+        self.time_predictor.update(delta);
+        self.time = time;
+    }
+}
+
+/// index_width returns the number of bits used to store an index into a dictionary of `len`
+/// labels, a dictionary of a single label needs no bits at all
+pub fn index_width(len: usize) -> u32 {
+    if len <= 1 {
+        return 0;
+    }
+    64 - (len as u64 - 1).leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use stream::BufferedWriter;
+    use super::{DictionaryEncoder, index_width};
+
+    #[test]
+    fn encode_labels() {
+        let w = BufferedWriter::new();
+        let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
+        let mut e = DictionaryEncoder::new(start_time, w);
+
+        e.encode(1482268055 + 10, "a");
+        e.encode(1482268055 + 20, "a");
+        e.encode(1482268055 + 30, "bc");
+        assert_eq!(e.labels(), ["a", "bc"]);
+
+        // the header, a dictionary of two labels, then a control bit, the first delta and index
+        // zero, a zero bit for the delta of delta and the unchanged label, then a zero bit for
+        // the delta of delta, a one bit for the changed label and index one
+        let bytes = e.close();
+        let expected_bytes: [u8; 27] = [0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 2, 0, 1, 97, 0, 2,
+                                        98, 99, 0, 20, 31, 128, 0, 0, 0, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(index_width(0), 0);
+        assert_eq!(index_width(1), 0);
+        assert_eq!(index_width(2), 1);
+        assert_eq!(index_width(3), 2);
+        assert_eq!(index_width(4), 2);
+        assert_eq!(index_width(5), 3);
+        assert_eq!(index_width(256), 8);
+        assert_eq!(index_width(257), 9);
+    }
+}
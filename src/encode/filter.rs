@@ -0,0 +1,264 @@
+use DataPoint;
+use encode::Encode;
+
+/// DeadbandEncoder
+///
+/// DeadbandEncoder drops `DataPoint`s whose value is within `tolerance` of the last value passed
+/// on to the inner encoder. When the value moves outside of the deadband, the last dropped
+/// `DataPoint` is passed on with the held value before the new `DataPoint`, so interpolating
+/// linearly between the `DataPoint`s kept, with `Interpolator`, reconstructs every dropped value
+/// to within `tolerance`.
+#[derive(Debug)]
+pub struct DeadbandEncoder<E: Encode> {
+    encoder: E,
+    tolerance: u64,
+    held: Option<DataPoint>, // last DataPoint passed on
+    dropped: Option<DataPoint>, // last DataPoint dropped since held
+}
+
+impl<E> DeadbandEncoder<E>
+    where E: Encode
+{
+    /// new creates a new DeadbandEncoder which passes the `DataPoint`s it keeps on to `encoder`
+    pub fn new(encoder: E, tolerance: u64) -> Self {
+        DeadbandEncoder {
+            encoder,
+            tolerance,
+            held: None,
+            dropped: None,
+        }
+    }
+}
+
+impl<E> Encode for DeadbandEncoder<E>
+    where E: Encode
+{
+    fn encode(&mut self, dp: DataPoint) {
+        if let Some(held) = self.held {
+            if (dp.value as i128 - held.value as i128).unsigned_abs() <= self.tolerance as u128 {
+                self.dropped = Some(dp);
+                return;
+            }
+            if let Some(dropped) = self.dropped.take() {
+                self.encoder.encode(DataPoint::new(dropped.time, held.value));
+            }
+        }
+
+        self.encoder.encode(dp);
+        self.held = Some(dp);
+    }
+
+    fn close(mut self) -> Box<[u8]> {
+        if let (Some(held), Some(dropped)) = (self.held, self.dropped) {
+            self.encoder.encode(DataPoint::new(dropped.time, held.value));
+        }
+
+        self.encoder.close()
+    }
+}
+
+/// SwingingDoorEncoder
+///
+/// SwingingDoorEncoder drops `DataPoint`s which lie within `tolerance` of a straight line between
+/// the `DataPoint`s either side of them, using the swinging door algorithm. Two doors pivot on
+/// the last `DataPoint` kept, `tolerance` above and below it, and open to let through each
+/// `DataPoint` which arrives. Once the doors would have to open past parallel to let a `DataPoint`
+/// through, no line from the last `DataPoint` kept passes within `tolerance` of every `DataPoint`
+/// since, so the `DataPoint` before it is kept, moved onto such a line. Interpolating linearly
+/// between the `DataPoint`s kept, with `Interpolator`, reconstructs every dropped value to within
+/// `tolerance`.
+#[derive(Debug)]
+pub struct SwingingDoorEncoder<E: Encode> {
+    encoder: E,
+    tolerance: f64, // tolerance of the doors, which leaves room for rounding kept values
+    kept: Option<DataPoint>, // last DataPoint passed on, where the doors pivot
+    last: Option<DataPoint>, // last DataPoint dropped since kept
+    upper: f64, // slope of the upper door
+    lower: f64, // slope of the lower door
+}
+
+impl<E> SwingingDoorEncoder<E>
+    where E: Encode
+{
+    /// new creates a new SwingingDoorEncoder which passes the `DataPoint`s it keeps on to
+    /// `encoder`. A `tolerance` of zero keeps every `DataPoint`
+    pub fn new(encoder: E, tolerance: u64) -> Self {
+        SwingingDoorEncoder {
+            encoder,
+            // values kept are rounded to the nearest integer, which moves the line between them
+            // by up to half
+            tolerance: tolerance as f64 - 0.5,
+            kept: None,
+            last: None,
+            upper: f64::NEG_INFINITY,
+            lower: f64::INFINITY,
+        }
+    }
+
+    // slopes returns the slopes the upper and lower doors need to let dp through
+    fn slopes(&self, kept: DataPoint, dp: DataPoint) -> (f64, f64) {
+        let dt = (dp.time - kept.time) as f64;
+        let dv = dp.value as f64 - kept.value as f64;
+        ((dv - self.tolerance) / dt, (dv + self.tolerance) / dt)
+    }
+
+    // keep passes dp on to the inner encoder and pivots the doors on it
+    fn keep(&mut self, dp: DataPoint) {
+        self.encoder.encode(dp);
+        self.kept = Some(dp);
+        self.last = None;
+        self.upper = f64::NEG_INFINITY;
+        self.lower = f64::INFINITY;
+    }
+
+    // keep_last keeps the last DataPoint dropped, moved onto the closest line between the doors
+    fn keep_last(&mut self) {
+        let (kept, last) = match (self.kept, self.last) {
+            (Some(kept), Some(last)) => (kept, last),
+            _ => return,
+        };
+
+        let dt = (last.time - kept.time) as f64;
+        let slope = ((last.value as f64 - kept.value as f64) / dt).max(self.upper).min(self.lower);
+        let value = (kept.value as f64 + slope * dt).round() as i64;
+        self.keep(DataPoint::new(last.time, value));
+    }
+}
+
+impl<E> Encode for SwingingDoorEncoder<E>
+    where E: Encode
+{
+    fn encode(&mut self, dp: DataPoint) {
+        let kept = match self.kept {
+            Some(kept) if dp.time > kept.time => kept,
+            _ => return self.keep(dp),
+        };
+
+        let (upper, lower) = self.slopes(kept, dp);
+        if self.upper.max(upper) <= self.lower.min(lower) {
+            self.upper = self.upper.max(upper);
+            self.lower = self.lower.min(lower);
+            self.last = Some(dp);
+            return;
+        }
+
+        if self.last.is_none() {
+            return self.keep(dp);
+        }
+        self.keep_last();
+
+        // pivot the doors on the DataPoint just kept and let dp through them
+        let kept = self.kept.unwrap();
+        let (upper, lower) = self.slopes(kept, dp);
+        if dp.time == kept.time || upper > lower {
+            return self.keep(dp);
+        }
+        self.upper = upper;
+        self.lower = lower;
+        self.last = Some(dp);
+    }
+
+    fn close(mut self) -> Box<[u8]> {
+        self.keep_last();
+        self.encoder.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {DataPoint, Decode, StdDecoder, StdEncoder, SimplePredictor};
+    use decode::interpolate::Interpolator;
+    use encode::Encode;
+    use stream::{BufferedReader, BufferedWriter};
+    use test_util::xorshift;
+    use super::{DeadbandEncoder, SwingingDoorEncoder};
+
+    // signal is a slow sine wave with some noise and occasional steps, sampled every 10 seconds
+    fn signal() -> Vec<DataPoint> {
+        let mut state = 0x853c_49e6_748f_ea9b;
+        (0..2000)
+            .map(|i| {
+                let wave = (i as f64 / 50.0).sin() * 1000.0;
+                let step = if (i / 300) % 2 == 0 { 0.0 } else { 500.0 };
+                let noise = (xorshift(&mut state) % 21) as f64 - 10.0;
+                DataPoint::new(1482892260 + i * 10, (wave + step + noise) as i64)
+            })
+            .collect()
+    }
+
+    fn encoder() -> StdEncoder<BufferedWriter, SimplePredictor> {
+        StdEncoder::new(1482892260, BufferedWriter::new(), SimplePredictor::new())
+    }
+
+    // assert_reconstructed checks that every DataPoint in datapoints can be reconstructed from
+    // bytes to within tolerance and returns the number of DataPoints kept
+    fn assert_reconstructed(bytes: Box<[u8]>, datapoints: &[DataPoint], tolerance: u64) -> usize {
+        let decoder = StdDecoder::new(BufferedReader::new(bytes.clone()), SimplePredictor::new());
+        let mut interpolator = Interpolator::new(decoder);
+        for dp in datapoints {
+            let value = interpolator.value_at(dp.time).unwrap().unwrap();
+            assert!((value - dp.value as f64).abs() <= tolerance as f64,
+                    "{:?} reconstructed as {}",
+                    dp,
+                    value);
+        }
+
+        let mut decoder = StdDecoder::new(BufferedReader::new(bytes), SimplePredictor::new());
+        let mut kept = 0;
+        while decoder.next().is_ok() {
+            kept += 1;
+        }
+        kept
+    }
+
+    #[test]
+    fn deadband_error_bound() {
+        let datapoints = signal();
+        for &tolerance in &[0, 5, 20, 100] {
+            let mut encoder = DeadbandEncoder::new(encoder(), tolerance);
+            for dp in &datapoints {
+                encoder.encode(*dp);
+            }
+
+            let kept = assert_reconstructed(encoder.close(), &datapoints, tolerance);
+            if tolerance == 0 {
+                assert_eq!(kept, datapoints.len());
+            } else {
+                assert!(kept < datapoints.len(), "{} kept with tolerance {}", kept, tolerance);
+            }
+        }
+    }
+
+    #[test]
+    fn swinging_door_error_bound() {
+        let datapoints = signal();
+        for &tolerance in &[0, 5, 20, 100] {
+            let mut encoder = SwingingDoorEncoder::new(encoder(), tolerance);
+            for dp in &datapoints {
+                encoder.encode(*dp);
+            }
+
+            let kept = assert_reconstructed(encoder.close(), &datapoints, tolerance);
+            if tolerance == 0 {
+                assert_eq!(kept, datapoints.len());
+            } else if tolerance >= 20 {
+                assert!(kept < datapoints.len() / 4, "{} kept with tolerance {}", kept, tolerance);
+            }
+        }
+    }
+
+    #[test]
+    fn swinging_door_straight_line() {
+        let mut encoder = SwingingDoorEncoder::new(encoder(), 1);
+        for i in 0..100 {
+            encoder.encode(DataPoint::new(1482892260 + i * 10, 5 + i as i64 * 3));
+        }
+
+        let decoder = StdDecoder::new(BufferedReader::new(encoder.close()), SimplePredictor::new());
+        let mut interpolator = Interpolator::new(decoder);
+        assert_eq!(interpolator.value_at(1482892260).unwrap(), Some(5.0));
+        assert_eq!(interpolator.value_at(1482892265).unwrap(), Some(6.5));
+        assert_eq!(interpolator.value_at(1482892260 + 990).unwrap(), Some(302.0));
+        assert_eq!(interpolator.value_at(1482892260 + 1000).unwrap(), None);
+    }
+}
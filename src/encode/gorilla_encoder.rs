@@ -0,0 +1,150 @@
+use {Bit, DataPoint};
+use encode::{Encode, write_window_xor};
+use stream::Write;
+
+/// GorillaEncoder
+///
+/// GorillaEncoder encodes `DataPoint`s with the exact bit layout described in the Gorilla paper,
+/// which is also the layout used by Beringei. The block starts with a 64 bit header holding the
+/// start time, the first delta is stored in 14 bits and values are written as the float64
+/// `value as f64`, XOR'd with the previous value using a 5 bit leading zeros and 6 bit length
+/// block.
+///
+/// The layout has no end of stream marker, so the number of encoded points has to be stored
+/// alongside the block and passed to `GorillaDecoder`.
+#[derive(Debug)]
+pub struct GorillaEncoder<T: Write> {
+    time: u64, // current time
+    delta: u64, // current time delta
+    value_bits: u64, // current float value as bits
+
+    leading_zeros: u32,
+    trailing_zeros: u32,
+
+    first: bool, // will next DataPoint be the first DataPoint encoded
+
+    w: T,
+}
+
+impl<T> GorillaEncoder<T>
+    where T: Write
+{
+    /// new creates a new GorillaEncoder whose starting timestamp is `start` and writes its
+    /// encoded bytes to `w`
+    pub fn new(start: u64, w: T) -> Self {
+        let mut e = GorillaEncoder {
+            time: start,
+            delta: 0,
+            value_bits: 0,
+            leading_zeros: 0xff, // 0xff is an initial sentinel value
+            trailing_zeros: 0,
+            first: true,
+            w,
+        };
+
+        // write timestamp header
+        e.w.write_bits(start, 64);
+
+        e
+    }
+
+    fn write_first(&mut self, time: u64, value_bits: u64) {
+        self.delta = time - self.time;
+        self.time = time;
+        self.value_bits = value_bits;
+
+        // the paper stores the first delta in 14 bits which spans just over 4 hours
+        self.w.write_bits(self.delta, 14);
+        self.w.write_bits(value_bits, 64);
+    }
+
+    fn write_next_timestamp(&mut self, time: u64) {
+        let delta = time - self.time;
+        let dod = delta.wrapping_sub(self.delta) as i32;
+
+        match dod {
+            0 => self.w.write_bit(Bit::Zero),
+            -63..=64 => {
+                self.w.write_bits(0b10, 2);
+                self.w.write_bits(dod as u64, 7);
+            }
+            -255..=256 => {
+                self.w.write_bits(0b110, 3);
+                self.w.write_bits(dod as u64, 9);
+            }
+            -2047..=2048 => {
+                self.w.write_bits(0b1110, 4);
+                self.w.write_bits(dod as u64, 12);
+            }
+            _ => {
+                self.w.write_bits(0b1111, 4);
+                self.w.write_bits(dod as u64, 32);
+            }
+        }
+
+        self.delta = delta;
+        self.time = time;
+    }
+}
+
+impl<T> Encode for GorillaEncoder<T>
+    where T: Write
+{
+    fn encode(&mut self, dp: DataPoint) {
+        let value_bits = (dp.value as f64).to_bits();
+
+        if self.first {
+            self.write_first(dp.time, value_bits);
+            self.first = false;
+            return;
+        }
+
+        self.write_next_timestamp(dp.time);
+
+        let xor = value_bits ^ self.value_bits;
+        self.value_bits = value_bits;
+        write_window_xor(&mut self.w, xor, &mut self.leading_zeros, &mut self.trailing_zeros);
+    }
+
+    fn close(self) -> Box<[u8]> {
+        self.w.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use encode::Encode;
+    use stream::BufferedWriter;
+    use super::GorillaEncoder;
+
+    #[test]
+    fn create_new_encoder() {
+        let w = BufferedWriter::new();
+        let start_time = 1427162400; // 2015-03-24T02:00:00+00:00
+        let e = GorillaEncoder::new(start_time, w);
+
+        let bytes = e.close();
+        let expected_bytes: [u8; 8] = [0, 0, 0, 0, 85, 16, 197, 32];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+
+    #[test]
+    fn encode_paper_example() {
+        // the example block from figure 2 of the Gorilla paper
+        let w = BufferedWriter::new();
+        let start_time = 1427162400; // 2015-03-24T02:00:00+00:00
+        let mut e = GorillaEncoder::new(start_time, w);
+
+        e.encode(DataPoint::new(start_time + 62, 12));
+        e.encode(DataPoint::new(start_time + 122, 12));
+        e.encode(DataPoint::new(start_time + 182, 24));
+
+        let bytes = e.close();
+        let expected_bytes: [u8; 21] = [0, 0, 0, 0, 85, 16, 197, 32, 0, 249, 0, 160, 0, 0, 0, 0,
+                                        0, 2, 252, 107, 6];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+}
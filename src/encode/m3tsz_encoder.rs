@@ -0,0 +1,553 @@
+use {Bit, DataPoint};
+use encode::Encode;
+use stream::Write;
+
+/// MARKER_OPCODE is the opcode that precedes special markers in the time stream. It overlaps
+/// with the first delta of delta bucket, so as in M3 a delta of delta written there whose top 7
+/// bits are zero is read back as a marker
+pub const MARKER_OPCODE: u64 = 0x100;
+
+/// MARKER_OPCODE_LEN is the length, in bits, of MARKER_OPCODE
+pub const MARKER_OPCODE_LEN: u32 = 9;
+
+/// MARKER_VALUE_LEN is the length, in bits, of the value following MARKER_OPCODE
+pub const MARKER_VALUE_LEN: u32 = 2;
+
+/// END_OF_STREAM_MARKER marks the end of the stream
+pub const END_OF_STREAM_MARKER: u64 = 0;
+
+/// ANNOTATION_MARKER is followed by a varint length and the bytes of an annotation
+pub const ANNOTATION_MARKER: u64 = 1;
+
+/// TIME_UNIT_MARKER is followed by a byte holding the new time unit
+pub const TIME_UNIT_MARKER: u64 = 2;
+
+// opcodes used when encoding values
+pub(crate) const OPCODE_UPDATE: u64 = 0;
+pub(crate) const OPCODE_NO_UPDATE: u64 = 1;
+pub(crate) const OPCODE_REPEAT: u64 = 1;
+pub(crate) const OPCODE_NO_REPEAT: u64 = 0;
+pub(crate) const OPCODE_FLOAT_MODE: u64 = 1;
+pub(crate) const OPCODE_INT_MODE: u64 = 0;
+pub(crate) const OPCODE_POSITIVE: u64 = 0;
+pub(crate) const OPCODE_NEGATIVE: u64 = 1;
+pub(crate) const OPCODE_UPDATE_SIG: u64 = 1;
+pub(crate) const OPCODE_NO_UPDATE_SIG: u64 = 0;
+pub(crate) const OPCODE_ZERO_SIG: u64 = 0;
+pub(crate) const OPCODE_NON_ZERO_SIG: u64 = 1;
+pub(crate) const OPCODE_UPDATE_MULT: u64 = 1;
+pub(crate) const OPCODE_NO_UPDATE_MULT: u64 = 0;
+pub(crate) const OPCODE_ZERO_VALUE_XOR: u64 = 0;
+pub(crate) const OPCODE_CONTAINED_VALUE_XOR: u64 = 0b10;
+pub(crate) const OPCODE_UNCONTAINED_VALUE_XOR: u64 = 0b11;
+
+pub(crate) const NUM_SIG_BITS: u32 = 6;
+pub(crate) const NUM_MULT_BITS: u32 = 3;
+pub(crate) const MAX_MULT: u8 = 6;
+
+// thresholds used to avoid changing the number of significant bits of integer diffs too often
+const SIG_DIFF_THRESHOLD: u8 = 3;
+const SIG_REPEAT_THRESHOLD: u8 = 5;
+
+// integer diffs outside of this range are stored as floats
+const MAX_INT: f64 = 9223372036854775807.0;
+const MIN_INT: f64 = -9223372036854775808.0;
+
+/// TimeUnit
+///
+/// The time units which M3TSZ can encode timestamps in. The discriminants match the bytes M3
+/// writes after a `TIME_UNIT_MARKER`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TimeUnit {
+    Second = 1,
+    Millisecond = 2,
+    Microsecond = 3,
+    Nanosecond = 4,
+}
+
+impl TimeUnit {
+    /// from_u8 converts the byte stored in a stream back into a TimeUnit
+    pub fn from_u8(b: u8) -> Option<TimeUnit> {
+        match b {
+            1 => Some(TimeUnit::Second),
+            2 => Some(TimeUnit::Millisecond),
+            3 => Some(TimeUnit::Microsecond),
+            4 => Some(TimeUnit::Nanosecond),
+            _ => None,
+        }
+    }
+
+    /// nanos returns the length of the time unit in nanoseconds
+    pub fn nanos(&self) -> u64 {
+        match *self {
+            TimeUnit::Second => 1_000_000_000,
+            TimeUnit::Millisecond => 1_000_000,
+            TimeUnit::Microsecond => 1_000,
+            TimeUnit::Nanosecond => 1,
+        }
+    }
+
+    // the number of value bits in each delta of delta bucket and in the default bucket
+    pub(crate) fn bucket_bits(&self) -> ([u32; 3], u32) {
+        match *self {
+            TimeUnit::Second => ([7, 9, 12], 32),
+            TimeUnit::Millisecond => ([10, 14, 17], 32),
+            TimeUnit::Microsecond => ([20, 24, 27], 64),
+            TimeUnit::Nanosecond => ([30, 34, 37], 64),
+        }
+    }
+
+    // the unit a stream starting at `start` begins in, None if `start` is not a multiple of it
+    pub(crate) fn initial(start: u64, unit: TimeUnit) -> Option<TimeUnit> {
+        if start.is_multiple_of(unit.nanos()) {
+            Some(unit)
+        } else {
+            None
+        }
+    }
+}
+
+/// M3TszEncoder
+///
+/// M3TszEncoder encodes `DataPoint`s using the M3TSZ format from M3DB. Times are nanoseconds
+/// since the epoch and are stored with the resolution of the current `TimeUnit`, which can change
+/// within the stream, and each point can carry an annotation. Values are encoded as the float64
+/// `value as f64`; with integer optimisation enabled values which are integers are stored as
+/// differences between integers rather than XORs of floats.
+#[derive(Debug)]
+pub struct M3TszEncoder<T: Write> {
+    time: u64, // current time
+    delta: i64, // current time delta
+    time_unit: Option<TimeUnit>, // current time unit
+    default_unit: TimeUnit, // time unit used by `encode`
+    annotation: Option<Vec<u8>>, // most recently written annotation
+
+    int_optimized: bool,
+    is_float: bool, // is the current value stored as a float
+    int_val: f64, // current integer value
+    max_mult: u8,
+
+    // state used to track the number of significant bits in integer diffs
+    num_sig: u8,
+    cur_highest_lower_sig: u8,
+    num_lower_sig: u8,
+
+    prev_float_bits: u64, // current float value as bits
+    prev_xor: u64,
+
+    first: bool, // will next DataPoint be the first DataPoint encoded
+
+    w: T,
+}
+
+impl<T> M3TszEncoder<T>
+    where T: Write
+{
+    /// new creates a new M3TszEncoder with integer optimisation enabled, whose starting timestamp
+    /// is `start` in nanoseconds and whose default time unit is `unit`
+    pub fn new(start: u64, unit: TimeUnit, w: T) -> Self {
+        M3TszEncoder::with_int_optimization(start, unit, true, w)
+    }
+
+    /// with_int_optimization creates a new M3TszEncoder, like `new`, which only uses the integer
+    /// optimisation if `int_optimized` is true
+    pub fn with_int_optimization(start: u64, unit: TimeUnit, int_optimized: bool, w: T) -> Self {
+        let mut e = M3TszEncoder {
+            time: start,
+            delta: 0,
+            time_unit: TimeUnit::initial(start, unit),
+            default_unit: unit,
+            annotation: None,
+            int_optimized,
+            is_float: false,
+            int_val: 0.0,
+            max_mult: 0,
+            num_sig: 0,
+            cur_highest_lower_sig: 0,
+            num_lower_sig: 0,
+            prev_float_bits: 0,
+            prev_xor: 0,
+            first: true,
+            w,
+        };
+
+        // the start time is always written in nanoseconds since it may not be a multiple of the
+        // time unit
+        e.w.write_bits(start, 64);
+
+        e
+    }
+
+    /// encode_with encodes `dp` with the time unit `unit` and the optional `annotation`.
+    /// Annotations are only written when they differ from the previously written annotation.
+    pub fn encode_with(&mut self, dp: DataPoint, unit: TimeUnit, annotation: Option<&[u8]>) {
+        self.write_annotation(annotation);
+        let unit_changed = self.write_time_unit(unit);
+        self.write_next_timestamp(dp.time, unit, unit_changed);
+
+        let value = dp.value as f64;
+        if self.first {
+            self.write_first_value(value);
+            self.first = false;
+        } else {
+            self.write_next_value(value);
+        }
+    }
+
+    fn write_marker(&mut self, marker: u64) {
+        self.w.write_bits(MARKER_OPCODE, MARKER_OPCODE_LEN);
+        self.w.write_bits(marker, MARKER_VALUE_LEN);
+    }
+
+    fn write_annotation(&mut self, annotation: Option<&[u8]>) {
+        let annotation = match annotation {
+            Some(annotation) if !annotation.is_empty() => annotation,
+            _ => return,
+        };
+
+        if self.annotation.as_ref().map(|a| &a[..]) == Some(annotation) {
+            return;
+        }
+
+        self.write_marker(ANNOTATION_MARKER);
+
+        // the length is stored minus one as a zig-zag varint
+        let len = annotation.len() as i64 - 1;
+        let mut ux = (len as u64) << 1;
+        if len < 0 {
+            ux = !ux;
+        }
+        while ux >= 0x80 {
+            self.w.write_byte((ux as u8) | 0x80);
+            ux >>= 7;
+        }
+        self.w.write_byte(ux as u8);
+
+        for byte in annotation {
+            self.w.write_byte(*byte);
+        }
+
+        self.annotation = Some(annotation.to_vec());
+    }
+
+    fn write_time_unit(&mut self, unit: TimeUnit) -> bool {
+        if self.time_unit == Some(unit) {
+            return false;
+        }
+
+        self.write_marker(TIME_UNIT_MARKER);
+        self.w.write_byte(unit as u8);
+        self.time_unit = Some(unit);
+
+        true
+    }
+
+    fn write_next_timestamp(&mut self, time: u64, unit: TimeUnit, unit_changed: bool) {
+        let delta = time.wrapping_sub(self.time) as i64;
+        let dod = delta.wrapping_sub(self.delta);
+        self.time = time;
+
+        if unit_changed {
+            // when the time unit changes the delta of delta is written in nanoseconds and the
+            // delta is reset
+            self.w.write_bits(dod as u64, 64);
+            self.delta = 0;
+            return;
+        }
+
+        self.delta = delta;
+
+        let dod = dod / unit.nanos() as i64;
+        if dod == 0 {
+            self.w.write_bit(Bit::Zero);
+            return;
+        }
+
+        let (bucket_bits, default_bits) = unit.bucket_bits();
+        let mut opcode = 0;
+        for (i, bits) in bucket_bits.iter().enumerate() {
+            opcode |= 1 << (i + 1);
+            let min = -(1 << (bits - 1));
+            let max = (1 << (bits - 1)) - 1;
+            if dod >= min && dod <= max {
+                self.w.write_bits(opcode, i as u32 + 2);
+                self.w.write_bits(dod as u64, *bits);
+                return;
+            }
+        }
+
+        self.w.write_bits(opcode | 1, bucket_bits.len() as u32 + 1);
+        self.w.write_bits(dod as u64, default_bits);
+    }
+
+    fn write_first_value(&mut self, value: f64) {
+        if !self.int_optimized {
+            self.write_full_float(value.to_bits());
+            return;
+        }
+
+        if value >= MAX_INT {
+            self.w.write_bits(OPCODE_FLOAT_MODE, 1);
+            self.write_full_float(value.to_bits());
+            self.is_float = true;
+            return;
+        }
+
+        self.w.write_bits(OPCODE_INT_MODE, 1);
+        self.int_val = value;
+
+        // the first value is stored as a diff from zero
+        let (diff, neg) = if value < 0.0 {
+            (-value, false)
+        } else {
+            (value, true)
+        };
+        let diff_bits = diff as u64;
+        let sig = num_sig(diff_bits);
+        self.write_int_sig_mult(sig, 0, false);
+        self.write_int_val_diff(diff_bits, neg);
+    }
+
+    fn write_next_value(&mut self, value: f64) {
+        if !self.int_optimized {
+            self.write_next_float(value.to_bits());
+            return;
+        }
+
+        let is_float = value >= MAX_INT;
+        let diff = self.int_val - value;
+        if is_float || diff >= MAX_INT || diff <= MIN_INT {
+            self.write_float_value(value.to_bits());
+            return;
+        }
+
+        self.write_int_value(value, diff);
+    }
+
+    fn write_float_value(&mut self, bits: u64) {
+        if !self.is_float {
+            // switching from integers to floats
+            self.w.write_bits(OPCODE_UPDATE, 1);
+            self.w.write_bits(OPCODE_NO_REPEAT, 1);
+            self.w.write_bits(OPCODE_FLOAT_MODE, 1);
+            self.write_full_float(bits);
+            self.is_float = true;
+            self.max_mult = 0;
+            return;
+        }
+
+        if bits == self.prev_float_bits {
+            self.w.write_bits(OPCODE_UPDATE, 1);
+            self.w.write_bits(OPCODE_REPEAT, 1);
+            return;
+        }
+
+        self.w.write_bits(OPCODE_NO_UPDATE, 1);
+        self.write_next_float(bits);
+    }
+
+    fn write_int_value(&mut self, value: f64, diff: f64) {
+        if diff == 0.0 && !self.is_float && self.max_mult == 0 {
+            self.w.write_bits(OPCODE_UPDATE, 1);
+            self.w.write_bits(OPCODE_REPEAT, 1);
+            return;
+        }
+
+        let (diff, neg) = if diff < 0.0 {
+            (-diff, true)
+        } else {
+            (diff, false)
+        };
+        let diff_bits = diff as u64;
+        let sig = self.track_new_sig(num_sig(diff_bits));
+
+        if self.num_sig != sig || self.is_float {
+            let float_changed = self.is_float;
+            self.w.write_bits(OPCODE_UPDATE, 1);
+            self.w.write_bits(OPCODE_NO_REPEAT, 1);
+            self.w.write_bits(OPCODE_INT_MODE, 1);
+            self.write_int_sig_mult(sig, 0, float_changed);
+            self.write_int_val_diff(diff_bits, neg);
+            self.is_float = false;
+        } else {
+            self.w.write_bits(OPCODE_NO_UPDATE, 1);
+            self.write_int_val_diff(diff_bits, neg);
+        }
+
+        self.int_val = value;
+    }
+
+    fn write_int_sig_mult(&mut self, sig: u8, mult: u8, float_changed: bool) {
+        if self.num_sig != sig {
+            self.w.write_bits(OPCODE_UPDATE_SIG, 1);
+            if sig == 0 {
+                self.w.write_bits(OPCODE_ZERO_SIG, 1);
+            } else {
+                self.w.write_bits(OPCODE_NON_ZERO_SIG, 1);
+                self.w.write_bits((sig - 1) as u64, NUM_SIG_BITS);
+            }
+        } else {
+            self.w.write_bits(OPCODE_NO_UPDATE_SIG, 1);
+        }
+        self.num_sig = sig;
+
+        if mult > self.max_mult {
+            self.w.write_bits(OPCODE_UPDATE_MULT, 1);
+            self.w.write_bits(mult as u64, NUM_MULT_BITS);
+            self.max_mult = mult;
+        } else if self.max_mult == mult && float_changed {
+            // M3 always rewrites the multiplier when switching back from floats
+            self.w.write_bits(OPCODE_UPDATE_MULT, 1);
+            self.w.write_bits(self.max_mult as u64, NUM_MULT_BITS);
+        } else {
+            self.w.write_bits(OPCODE_NO_UPDATE_MULT, 1);
+        }
+    }
+
+    fn write_int_val_diff(&mut self, diff_bits: u64, neg: bool) {
+        if neg {
+            self.w.write_bits(OPCODE_NEGATIVE, 1);
+        } else {
+            self.w.write_bits(OPCODE_POSITIVE, 1);
+        }
+        let num_sig = self.num_sig as u32;
+        self.w.write_bits(diff_bits, num_sig);
+    }
+
+    // track_new_sig returns the number of significant bits to use for an integer diff with
+    // `sig` significant bits, only shrinking after several consecutive smaller diffs
+    fn track_new_sig(&mut self, sig: u8) -> u8 {
+        let mut new_sig = self.num_sig;
+
+        if sig > self.num_sig {
+            new_sig = sig;
+        } else if self.num_sig - sig >= SIG_DIFF_THRESHOLD {
+            if self.num_lower_sig == 0 || sig > self.cur_highest_lower_sig {
+                self.cur_highest_lower_sig = sig;
+            }
+
+            self.num_lower_sig += 1;
+            if self.num_lower_sig >= SIG_REPEAT_THRESHOLD {
+                new_sig = self.cur_highest_lower_sig;
+                self.num_lower_sig = 0;
+            }
+        } else {
+            self.num_lower_sig = 0;
+        }
+
+        new_sig
+    }
+
+    fn write_full_float(&mut self, bits: u64) {
+        self.prev_float_bits = bits;
+        self.prev_xor = bits;
+        self.w.write_bits(bits, 64);
+    }
+
+    fn write_next_float(&mut self, bits: u64) {
+        let xor = self.prev_float_bits ^ bits;
+        self.write_xor(xor);
+        self.prev_xor = xor;
+        self.prev_float_bits = bits;
+    }
+
+    fn write_xor(&mut self, xor: u64) {
+        if xor == 0 {
+            self.w.write_bits(OPCODE_ZERO_VALUE_XOR, 1);
+            return;
+        }
+
+        let (prev_leading, prev_trailing) = leading_and_trailing_zeros(self.prev_xor);
+        let (leading, trailing) = leading_and_trailing_zeros(xor);
+
+        if leading >= prev_leading && trailing >= prev_trailing {
+            self.w.write_bits(OPCODE_CONTAINED_VALUE_XOR, 2);
+            self.w.write_bits(xor >> prev_trailing, 64 - prev_leading - prev_trailing);
+            return;
+        }
+
+        // the number of meaningful bits is at least 1 so it is stored minus one in 6 bits
+        let meaningful_bits = 64 - leading - trailing;
+        self.w.write_bits(OPCODE_UNCONTAINED_VALUE_XOR, 2);
+        self.w.write_bits(leading as u64, 6);
+        self.w.write_bits((meaningful_bits - 1) as u64, 6);
+        self.w.write_bits(xor >> trailing, meaningful_bits);
+    }
+}
+
+// num_sig returns the number of significant bits in `bits`
+pub(crate) fn num_sig(bits: u64) -> u8 {
+    (64 - bits.leading_zeros()) as u8
+}
+
+// leading_and_trailing_zeros treats zero as having 64 leading and no trailing zeros
+pub(crate) fn leading_and_trailing_zeros(bits: u64) -> (u32, u32) {
+    if bits == 0 {
+        return (64, 0);
+    }
+    (bits.leading_zeros(), bits.trailing_zeros())
+}
+
+impl<T> Encode for M3TszEncoder<T>
+    where T: Write
+{
+    fn encode(&mut self, dp: DataPoint) {
+        let unit = self.default_unit;
+        self.encode_with(dp, unit, None);
+    }
+
+    fn close(mut self) -> Box<[u8]> {
+        self.write_marker(END_OF_STREAM_MARKER);
+        self.w.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use encode::Encode;
+    use stream::BufferedWriter;
+    use super::{M3TszEncoder, TimeUnit};
+
+    #[test]
+    fn create_new_encoder() {
+        let w = BufferedWriter::new();
+        let start_time = 1427162400 * 1_000_000_000; // 2015-03-24T02:00:00+00:00
+        let e = M3TszEncoder::new(start_time, TimeUnit::Second, w);
+
+        let bytes = e.close();
+        let expected_bytes: [u8; 10] = [19, 206, 76, 164, 48, 203, 64, 0, 128, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+
+    #[test]
+    fn encode_datapoint() {
+        let w = BufferedWriter::new();
+        let start_time = 1427162400 * 1_000_000_000; // 2015-03-24T02:00:00+00:00
+        let mut e = M3TszEncoder::new(start_time, TimeUnit::Second, w);
+
+        e.encode(DataPoint::new(start_time + 10 * 1_000_000_000, 1));
+
+        let bytes = e.close();
+        let expected_bytes: [u8; 12] = [19, 206, 76, 164, 48, 203, 64, 0, 133, 48, 28, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+
+    #[test]
+    fn encode_marker_prefixed_delta_of_delta() {
+        let w = BufferedWriter::new();
+        let start_time = 1427162400 * 1_000_000_000; // 2015-03-24T02:00:00+00:00
+        let mut e = M3TszEncoder::new(start_time, TimeUnit::Millisecond, w);
+
+        // a delta of delta of 5ms is written in the 10 bit bucket as 10 0000000101, which starts
+        // with MARKER_OPCODE, just as M3 writes it
+        e.encode(DataPoint::new(start_time + 5_000_000, 1));
+
+        let bytes = e.close();
+        let expected_bytes: [u8; 13] = [19, 206, 76, 164, 48, 203, 64, 0, 128, 86, 3, 128, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+}
@@ -1,4 +1,5 @@
-use DataPoint;
+use {Bit, DataPoint};
+use stream::Write;
 
 /// Encode
 ///
@@ -8,4 +9,105 @@ pub trait Encode {
     fn close(self) -> Box<[u8]>;
 }
 
-pub mod std_encoder;
\ No newline at end of file
+/// write_delta_of_delta writes `dod`, the difference between the delta since the last timestamp
+/// and the delta that was predicted, using variable length encoding. A delta of delta of zero is
+/// stored as a single zero bit, so one stored in 32 bits can be used to mark the end of the
+/// stream, see END_MARKER
+pub fn write_delta_of_delta<T: Write>(w: &mut T, dod: i32) {
+    match dod {
+        0 => {
+            w.write_bit(Bit::Zero);
+        }
+        -63..=64 => {
+            w.write_bits(0b10, 2);
+            w.write_bits(dod as u64, 7);
+        }
+        -255..=256 => {
+            w.write_bits(0b110, 3);
+            w.write_bits(dod as u64, 9);
+        }
+        -2047..=2048 => {
+            w.write_bits(0b1110, 4);
+            w.write_bits(dod as u64, 12);
+        }
+        _ => {
+            w.write_bits(0b1111, 4);
+            w.write_bits(dod as u64, 32);
+        }
+    }
+}
+
+/// write_xor writes `xor`, a value XOR'd with the value that was predicted. `leading_zeros`
+/// holds the number of leading zeros last written, the number of leading zeros is only written
+/// again when it changes. It should start at 64, which no non zero xor has
+pub fn write_xor<T: Write>(w: &mut T, xor: u64, leading_zeros: &mut u32) {
+    if xor == 0 {
+        // if the prediction was exact just store a single zero bit
+        w.write_bit(Bit::Zero);
+        return;
+    }
+
+    w.write_bit(Bit::One);
+
+    let n = xor.leading_zeros();
+    if n == *leading_zeros {
+        // the same number of leading zeros as the last xor written, so only the significant
+        // digits need to be stored
+        w.write_bit(Bit::Zero);
+    } else {
+        // otherwise store a control bit and use 6 bits to store the number of leading zeros
+        // before the significant digits
+        w.write_bit(Bit::One);
+        w.write_bits(n as u64, 6);
+        *leading_zeros = n;
+    }
+    w.write_bits(xor, 64 - n);
+}
+
+/// write_window_xor writes `xor` using the layout of the Gorilla paper, which Prometheus also
+/// uses. The significant digits are stored within the window of the last xor written if they
+/// fit, otherwise a new window is stored with 5 bits of leading zeros and 6 bits of length.
+/// `leading_zeros` and `trailing_zeros` hold the last window, 0xff leading zeros means there is
+/// no window yet
+pub(crate) fn write_window_xor<T: Write>(w: &mut T,
+                                         xor: u64,
+                                         leading_zeros: &mut u32,
+                                         trailing_zeros: &mut u32) {
+    if xor == 0 {
+        w.write_bit(Bit::Zero);
+        return;
+    }
+
+    w.write_bit(Bit::One);
+
+    // leading zeros are stored in 5 bits so they have to be clamped to 31
+    let lz = ::std::cmp::min(xor.leading_zeros(), 31);
+    let tz = xor.trailing_zeros();
+
+    if *leading_zeros != 0xff && lz >= *leading_zeros && tz >= *trailing_zeros {
+        // the significant digits of this xor fit within the previous window
+        let significant_digits = 64 - *leading_zeros - *trailing_zeros;
+        w.write_bit(Bit::Zero);
+        w.write_bits(xor >> *trailing_zeros, significant_digits);
+        return;
+    }
+
+    *leading_zeros = lz;
+    *trailing_zeros = tz;
+
+    // significant_digits is at least 1, so a value of 64 is stored as 0 in 6 bits
+    let significant_digits = 64 - lz - tz;
+    w.write_bit(Bit::One);
+    w.write_bits(lz as u64, 5);
+    w.write_bits(significant_digits as u64, 6);
+    w.write_bits(xor >> tz, significant_digits);
+}
+
+pub mod std_encoder;
+pub mod prometheus_encoder;
+pub mod gorilla_encoder;
+pub mod m3tsz_encoder;
+pub mod multi_encoder;
+pub mod state_encoder;
+pub mod dictionary_encoder;
+pub mod filter;
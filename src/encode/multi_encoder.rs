@@ -0,0 +1,164 @@
+use Bit;
+use stream::Write;
+use encode;
+use encode::std_encoder::{END_MARKER, END_MARKER_LEN};
+use predictor::{Predictor, TimestampPredictor, DeltaOfDeltaPredictor};
+
+// Column holds the state needed to encode the values of one field
+#[derive(Debug, Clone)]
+struct Column<P: Predictor> {
+    predictor: P,
+    leading_zeros: u32, // leading zeros in the last xor whose leading zeros were written
+}
+
+/// MultiEncoder
+///
+/// MultiEncoder encodes rows of values which share a timestamp, such as the fields of a group
+/// recorded at the same instants. The timestamps are written once per row, in the same way as
+/// `StdEncoder`, followed by the value of each field XOR'd with the value predicted by the
+/// field's own `Predictor`. Every row must hold one value per predictor, and the stream is
+/// terminated by END_MARKER.
+#[derive(Debug, Clone)]
+pub struct MultiEncoder<T: Write, P: Predictor, Q: TimestampPredictor = DeltaOfDeltaPredictor> {
+    time: u64, // current time
+    time_predictor: Q, // predicts the next time delta
+    columns: Vec<Column<P>>,
+
+    first: bool, // will next row be the first row encoded
+
+    w: T,
+}
+
+impl<T, P> MultiEncoder<T, P>
+    where T: Write,
+    P: Predictor
+{
+    /// new creates a new MultiEncoder whose starting timestamp is `start`, which writes its
+    /// encoded bytes to `w` and uses one predictor from `predictors` for each field
+    pub fn new(start: u64, w: T, predictors: Vec<P>) -> Self {
+        MultiEncoder::with_timestamp_predictor(start, w, predictors, DeltaOfDeltaPredictor::new())
+    }
+}
+
+impl<T, P, Q> MultiEncoder<T, P, Q>
+    where T: Write,
+    P: Predictor,
+    Q: TimestampPredictor
+{
+    /// with_timestamp_predictor creates a new MultiEncoder whose starting timestamp is `start`,
+    /// which writes its encoded bytes to `w`, uses one predictor from `predictors` for each field
+    /// and uses `q` to predict the delta between timestamps
+    pub fn with_timestamp_predictor(start: u64, w: T, predictors: Vec<P>, q: Q) -> Self {
+        assert!(!predictors.is_empty(), "there must be at least one field");
+
+        let columns = predictors.into_iter()
+            .map(|predictor| {
+                Column {
+                    predictor,
+                    leading_zeros: 64, // 64 is an initial sentinel value
+                }
+            })
+            .collect();
+
+        let mut e = MultiEncoder {
+            time: start,
+            time_predictor: q,
+            columns,
+            first: true,
+            w,
+        };
+
+        // write timestamp header
+        e.w.write_bits(start, 64);
+
+        e
+    }
+
+    /// fields returns the number of values in each row
+    pub fn fields(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// encode writes a row of `values`, one for each field, at `time`
+    pub fn encode(&mut self, time: u64, values: &[i64]) {
+        assert_eq!(values.len(), self.columns.len(), "a row must hold one value per field");
+
+        if self.first {
+            self.write_first_timestamp(time);
+            for (column, &value) in self.columns.iter_mut().zip(values) {
+                // store the first value exactly
+                column.predictor.update(value as u64);
+                self.w.write_bits(value as u64, 64);
+            }
+            self.first = false;
+            return;
+        }
+
+        self.write_next_timestamp(time);
+        for (column, &value) in self.columns.iter_mut().zip(values) {
+            write_next_value(&mut self.w, column, value as u64);
+        }
+    }
+
+    /// close writes END_MARKER and returns the encoded bytes
+    pub fn close(mut self) -> Box<[u8]> {
+        self.w.write_bits(END_MARKER, END_MARKER_LEN);
+        self.w.close()
+    }
+
+    fn write_first_timestamp(&mut self, time: u64) {
+        let delta = time - self.time;
+        self.time = time;
+        self.time_predictor.update(delta);
+
+        // write one control bit so we can distinguish a stream which contains only an initial
+        // timestamp, this assumes the first bit of the END_MARKER is 1
+        self.w.write_bit(Bit::Zero);
+
+        // store the first delta with 14 bits as StdEncoder does
+        self.w.write_bits(delta, 14);
+    }
+
+    fn write_next_timestamp(&mut self, time: u64) {
+        let delta = time - self.time;
+        let dod = delta.wrapping_sub(self.time_predictor.predict_next()) as i32;
+
+        encode::write_delta_of_delta(&mut self.w, dod);
+
+        self.time_predictor.update(delta);
+        self.time = time;
+    }
+}
+
+// write_next_value writes value_bits XOR'd with the value predicted for column
+fn write_next_value<T: Write, P: Predictor>(w: &mut T, column: &mut Column<P>, value_bits: u64) {
+    let xor = value_bits ^ column.predictor.predict_next();
+    column.predictor.update(value_bits);
+    encode::write_xor(w, xor, &mut column.leading_zeros);
+}
+
+#[cfg(test)]
+mod tests {
+    use stream::BufferedWriter;
+    use predictor::SimplePredictor;
+    use super::MultiEncoder;
+
+    #[test]
+    fn encode_rows() {
+        let w = BufferedWriter::new();
+        let predictors = vec![SimplePredictor::new(), SimplePredictor::new()];
+        let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
+        let mut e = MultiEncoder::new(start_time, w, predictors);
+
+        e.encode(1482268055 + 10, &[1, 2]);
+        e.encode(1482268055 + 20, &[1, 3]);
+
+        // the header, the first delta and both values in full, then a zero bit for the delta of
+        // delta of the second row, a zero bit for the unchanged value and the xor of 2 and 3
+        let bytes = e.close();
+        let expected_bytes: [u8; 32] = [0, 0, 0, 0, 88, 89, 157, 151, 0, 20, 0, 0, 0, 0, 0, 0, 0,
+                                        2, 0, 0, 0, 0, 0, 0, 0, 4, 127, 252, 0, 0, 0, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+}
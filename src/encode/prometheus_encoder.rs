@@ -0,0 +1,235 @@
+use {Bit, DataPoint};
+use encode::{Encode, write_window_xor};
+use stream::Write;
+
+/// MAX_POINTS is the number of `DataPoint`s which fit in a Prometheus chunk, whose sample count
+/// is stored in 16 bits
+pub const MAX_POINTS: u16 = u16::MAX;
+
+/// PrometheusEncoder
+///
+/// PrometheusEncoder encodes `DataPoint`s using the layout of the XOR chunks stored by the
+/// Prometheus TSDB. The chunk starts with a 16 bit sample count, the first time is a varint and
+/// the second a uvarint delta, then delta of deltas are stored in 14, 17, 20 or 64 bits. Values
+/// are written as the float64 `value as f64` and XOR'd with the previous value, storing both the
+/// leading and trailing zeros.
+///
+/// Prometheus times are milliseconds since the epoch and the chunk can hold at most MAX_POINTS
+/// points, `is_full` reports when a new chunk has to be started.
+#[derive(Debug)]
+pub struct PrometheusEncoder<T: Write> {
+    time: u64, // current time
+    delta: u64, // current time delta
+    value_bits: u64, // current float value as bits
+
+    leading_zeros: u32,
+    trailing_zeros: u32,
+
+    count: u16, // number of DataPoints encoded so far
+
+    w: T,
+}
+
+impl<T> PrometheusEncoder<T>
+    where T: Write
+{
+    /// new creates a new PrometheusEncoder which writes its encoded bytes to `w`
+    pub fn new(w: T) -> Self {
+        let mut e = PrometheusEncoder {
+            time: 0,
+            delta: 0,
+            value_bits: 0,
+            leading_zeros: 0xff, // 0xff is an initial sentinel value
+            trailing_zeros: 0,
+            count: 0,
+            w,
+        };
+
+        // reserve space for the sample count header, it is filled in when the encoder is closed
+        e.w.write_bits(0, 16);
+
+        e
+    }
+
+    /// is_full returns true once the chunk holds MAX_POINTS `DataPoint`s, encoding another
+    /// `DataPoint` will panic
+    pub fn is_full(&self) -> bool {
+        self.count == MAX_POINTS
+    }
+
+    fn write_uvarint(&mut self, mut x: u64) {
+        while x >= 0x80 {
+            self.w.write_byte((x as u8) | 0x80);
+            x >>= 7;
+        }
+        self.w.write_byte(x as u8);
+    }
+
+    fn write_varint(&mut self, x: i64) {
+        // zig-zag encoding so small negative numbers are also short
+        let mut ux = (x as u64) << 1;
+        if x < 0 {
+            ux = !ux;
+        }
+        self.write_uvarint(ux);
+    }
+
+    fn write_next_timestamp(&mut self, time: u64) {
+        let delta = time.wrapping_sub(self.time);
+        let dod = delta.wrapping_sub(self.delta) as i64;
+
+        match dod {
+            0 => self.w.write_bit(Bit::Zero),
+            -8191..=8192 => {
+                self.w.write_bits(0b10, 2);
+                self.w.write_bits(dod as u64, 14);
+            }
+            -65535..=65536 => {
+                self.w.write_bits(0b110, 3);
+                self.w.write_bits(dod as u64, 17);
+            }
+            -524287..=524288 => {
+                self.w.write_bits(0b1110, 4);
+                self.w.write_bits(dod as u64, 20);
+            }
+            _ => {
+                self.w.write_bits(0b1111, 4);
+                self.w.write_bits(dod as u64, 64);
+            }
+        }
+
+        self.delta = delta;
+        self.time = time;
+    }
+
+    fn write_next_value(&mut self, value_bits: u64) {
+        let xor = value_bits ^ self.value_bits;
+        self.value_bits = value_bits;
+
+        write_window_xor(&mut self.w, xor, &mut self.leading_zeros, &mut self.trailing_zeros);
+    }
+}
+
+impl<T> Encode for PrometheusEncoder<T>
+    where T: Write
+{
+    fn encode(&mut self, dp: DataPoint) {
+        // check before anything is written so a full chunk is left intact
+        assert!(!self.is_full(), "a Prometheus chunk can hold at most {} points", MAX_POINTS);
+
+        let value_bits = (dp.value as f64).to_bits();
+
+        match self.count {
+            0 => {
+                self.write_varint(dp.time as i64);
+                self.w.write_bits(value_bits, 64);
+                self.value_bits = value_bits;
+            }
+            1 => {
+                let delta = dp.time.wrapping_sub(self.time);
+                self.write_uvarint(delta);
+                self.write_next_value(value_bits);
+                self.delta = delta;
+            }
+            _ => {
+                self.write_next_timestamp(dp.time);
+                self.write_next_value(value_bits);
+            }
+        }
+
+        self.time = dp.time;
+        self.count += 1;
+    }
+
+    fn close(self) -> Box<[u8]> {
+        let mut bytes = self.w.close();
+        bytes[0] = (self.count >> 8) as u8;
+        bytes[1] = self.count as u8;
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use encode::Encode;
+    use stream::BufferedWriter;
+    use super::{PrometheusEncoder, MAX_POINTS};
+
+    #[test]
+    fn create_new_encoder() {
+        let w = BufferedWriter::new();
+        let e = PrometheusEncoder::new(w);
+
+        let bytes = e.close();
+        let expected_bytes: [u8; 2] = [0, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+
+    #[test]
+    fn encode_full_chunk() {
+        let mut e = PrometheusEncoder::new(BufferedWriter::new());
+        for i in 0..MAX_POINTS as u64 {
+            assert!(!e.is_full());
+            e.encode(DataPoint::new(1500000000000 + i * 15000, 1));
+        }
+        assert!(e.is_full());
+
+        let bytes = e.close();
+        assert_eq!(bytes[..2], [255, 255]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 65535 points")]
+    fn encode_past_full_chunk() {
+        let mut e = PrometheusEncoder::new(BufferedWriter::new());
+        for i in 0..MAX_POINTS as u64 + 1 {
+            e.encode(DataPoint::new(1500000000000 + i * 15000, 1));
+        }
+    }
+
+    #[test]
+    fn encode_datapoint() {
+        let w = BufferedWriter::new();
+        let mut e = PrometheusEncoder::new(w);
+
+        e.encode(DataPoint::new(1500000000000, 1));
+
+        let bytes = e.close();
+        let expected_bytes: [u8; 16] = [0, 1, 128, 224, 188, 239, 167, 87, 63, 240, 0, 0, 0, 0,
+                                        0, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+
+    #[test]
+    fn encode_multiple_datapoints() {
+        let w = BufferedWriter::new();
+        let mut e = PrometheusEncoder::new(w);
+
+        // the deltas of deltas cover every bucket, both positive and negative
+        let start = 1500000000000;
+        e.encode(DataPoint::new(start, 1));
+        e.encode(DataPoint::new(start + 15000, 2));
+        e.encode(DataPoint::new(start + 30000, 2));
+        e.encode(DataPoint::new(start + 45000, 3));
+        e.encode(DataPoint::new(start + 65000, -7));
+        e.encode(DataPoint::new(start + 125000, 1024));
+        e.encode(DataPoint::new(start + 525000, 1024));
+        e.encode(DataPoint::new(start + 526000, 1));
+        e.encode(DataPoint::new(start + 100526000, 98765));
+        e.encode(DataPoint::new(start + 100541000, 98765));
+        e.encode(DataPoint::new(start + 100555000, 12));
+
+        let bytes = e.close();
+        let expected_bytes: [u8; 69] = [0, 11, 128, 224, 188, 239, 167, 87, 63, 240, 0, 0, 0, 0,
+                                        0, 0, 152, 117, 194, 95, 255, 27, 1, 201, 196, 96, 58,
+                                        0, 92, 156, 64, 160, 35, 229, 48, 32, 116, 244, 180, 79,
+                                        236, 120, 0, 0, 0, 0, 47, 174, 232, 198, 22, 255, 132,
+                                        14, 111, 255, 255, 255, 255, 253, 5, 44, 204, 47, 6, 32,
+                                        26, 3, 154];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+}
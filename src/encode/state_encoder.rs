@@ -0,0 +1,209 @@
+use std::mem;
+
+use {Bit, DataPoint};
+use stream::Write;
+use encode::{self, Encode};
+use encode::std_encoder::{END_MARKER, END_MARKER_LEN};
+use predictor::{TimestampPredictor, DeltaOfDeltaPredictor};
+
+/// MAX_RUN is the largest number of `DataPoint`s stored in a single run, longer runs are split so
+/// that the timestamps held for the current run stay small
+pub const MAX_RUN: u64 = 4096;
+
+/// StateEncoder
+///
+/// StateEncoder encodes series whose values are states which change rarely, such as booleans
+/// stored as 0 and 1 or small enums of status codes. `DataPoint`s are grouped into runs which
+/// share a value, and each run is written as the value, the number of `DataPoint`s in the run
+/// and then their timestamps, which are encoded in the same way as `StdEncoder`. Values cost a
+/// few bits per run rather than at least one bit per `DataPoint`, a run whose value is the same
+/// as the run before the last, such as a boolean flipping back, costs a single bit. Since a run
+/// is only written once its value changes, the timestamps of the current run, at most MAX_RUN of
+/// them, are held until then. The stream is terminated by END_MARKER.
+#[derive(Debug, Clone)]
+pub struct StateEncoder<T: Write, Q: TimestampPredictor = DeltaOfDeltaPredictor> {
+    time: u64, // current time
+    time_predictor: Q, // predicts the next time delta
+
+    run: Vec<u64>, // timestamps of the current run
+    value: i64, // value of the current run
+    previous: Option<i64>, // value of the run written last
+    before: Option<i64>, // value of the run written before the last
+
+    first: bool, // will next DataPoint be the first DataPoint encoded
+
+    w: T,
+}
+
+impl<T> StateEncoder<T>
+    where T: Write
+{
+    /// new creates a new StateEncoder whose starting timestamp is `start` and writes its encoded
+    /// bytes to `w`
+    pub fn new(start: u64, w: T) -> Self {
+        StateEncoder::with_timestamp_predictor(start, w, DeltaOfDeltaPredictor::new())
+    }
+}
+
+impl<T, Q> StateEncoder<T, Q>
+    where T: Write,
+    Q: TimestampPredictor
+{
+    /// with_timestamp_predictor creates a new StateEncoder whose starting timestamp is `start`,
+    /// which writes its encoded bytes to `w` and uses `q` to predict the delta between timestamps
+    pub fn with_timestamp_predictor(start: u64, w: T, q: Q) -> Self {
+        let mut e = StateEncoder {
+            time: start,
+            time_predictor: q,
+            run: Vec::new(),
+            value: 0,
+            previous: None,
+            before: None,
+            first: true,
+            w,
+        };
+
+        // write timestamp header
+        e.w.write_bits(start, 64);
+
+        e
+    }
+
+    // write_run writes the value and length of the current run followed by its timestamps
+    fn write_run(&mut self) {
+        if self.run.is_empty() {
+            return;
+        }
+
+        // one control bit so a run can be distinguished from END_MARKER, whose first bit is 1
+        self.w.write_bit(Bit::Zero);
+        self.write_value();
+        self.write_len();
+
+        // take the timestamps so the run can be written without cloning them
+        let mut run = mem::take(&mut self.run);
+        for &time in &run {
+            if self.first {
+                self.write_first_timestamp(time);
+                self.first = false;
+            } else {
+                self.write_next_timestamp(time);
+            }
+        }
+        run.clear();
+        self.run = run;
+
+        self.before = self.previous;
+        self.previous = Some(self.value);
+    }
+
+    fn write_value(&mut self) {
+        let previous = match self.previous {
+            Some(previous) => previous,
+            None => {
+                // store the first value exactly
+                self.w.write_bits(self.value as u64, 64);
+                return;
+            }
+        };
+
+        if self.before == Some(self.value) {
+            // the value is the same as the run before the last so just store a single zero bit
+            self.w.write_bit(Bit::Zero);
+            return;
+        }
+
+        // store the xor with the last value, which only equals the value if a run was split,
+        // the leading zeros are capped so that an xor of zero is stored as a single zero bit
+        let xor = self.value as u64 ^ previous as u64;
+        let leading_zeros = xor.leading_zeros().min(63);
+        self.w.write_bit(Bit::One);
+        self.w.write_bits(leading_zeros as u64, 6);
+        self.w.write_bits(xor, 64 - leading_zeros);
+    }
+
+    fn write_len(&mut self) {
+        // store the length minus one, which is less than MAX_RUN, using variable length encoding
+        let n = self.run.len() as u64 - 1;
+        match n {
+            0 => {
+                self.w.write_bit(Bit::Zero);
+            }
+            1..=63 => {
+                self.w.write_bits(0b10, 2);
+                self.w.write_bits(n, 6);
+            }
+            _ => {
+                self.w.write_bits(0b11, 2);
+                self.w.write_bits(n, 12);
+            }
+        }
+    }
+
+    fn write_first_timestamp(&mut self, time: u64) {
+        let delta = time - self.time;
+        self.time = time;
+        self.time_predictor.update(delta);
+
+        // store the first delta with 14 bits as StdEncoder does
+        self.w.write_bits(delta, 14);
+    }
+
+    fn write_next_timestamp(&mut self, time: u64) {
+        let delta = time - self.time;
+        let dod = delta.wrapping_sub(self.time_predictor.predict_next()) as i32;
+
+        // store the delta of delta using variable length encoding
+        encode::write_delta_of_delta(&mut self.w, dod);
+
+        self.time_predictor.update(delta);
+        self.time = time;
+    }
+}
+
+impl<T, Q> Encode for StateEncoder<T, Q>
+    where T: Write,
+    Q: TimestampPredictor
+{
+    fn encode(&mut self, dp: DataPoint) {
+        if !self.run.is_empty() && (dp.value != self.value || self.run.len() as u64 == MAX_RUN) {
+            self.write_run();
+        }
+
+        self.value = dp.value;
+        self.run.push(dp.time);
+    }
+
+    fn close(mut self) -> Box<[u8]> {
+        self.write_run();
+        self.w.write_bits(END_MARKER, END_MARKER_LEN);
+        self.w.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {DataPoint, Encode};
+    use stream::BufferedWriter;
+    use super::StateEncoder;
+
+    #[test]
+    fn encode_runs() {
+        let w = BufferedWriter::new();
+        let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
+        let mut e = StateEncoder::new(start_time, w);
+
+        e.encode(DataPoint::new(1482268055 + 10, 1));
+        e.encode(DataPoint::new(1482268055 + 20, 1));
+        e.encode(DataPoint::new(1482268055 + 30, 0));
+
+        // the header, then a control bit, the first value in full, a length of two, the first
+        // delta and a zero bit for the delta of delta, then a control bit, the xor of 1 and 0
+        // with its leading zeros, a length of one and a zero bit for the delta of delta
+        let bytes = e.close();
+        let expected_bytes: [u8; 25] = [0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 0, 0, 0, 0, 0, 192,
+                                        128, 20, 127, 158, 0, 0, 0, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+}
@@ -1,9 +1,10 @@
 use std::mem;
 
 use {Bit, DataPoint};
-use encode::Encode;
+use encode::{self, Encode};
 use stream::Write;
-use predictor::Predictor;
+use predictor::{Predictor, TimestampPredictor, DeltaOfDeltaPredictor};
+use checksum;
 
 // END_MARKER relies on the fact that when we encode the delta of delta for a number that requires
 // more than 12 bits we write four control bits 1111 followed by the 32 bits of the value. Since
@@ -16,13 +17,87 @@ pub const END_MARKER: u64 = 0b111100000000000000000000000000000000;
 /// END_MARKER_LEN is the length, in bits, of END_MARKER
 pub const END_MARKER_LEN: u32 = 36;
 
+// SYNC_ESCAPE uses the same trick as END_MARKER, a delta of delta of -2^31 stored in 32 bits is
+// decoded as +2^31 so the encoder never writes it for a regular DataPoint and it can be used to
+// signal a sync point instead
+
+/// SYNC_ESCAPE is a special bit sequence used to indicate that a sync point follows
+pub const SYNC_ESCAPE: u64 = 0b1111_1000_0000_0000_0000_0000_0000_0000_0000;
+
+/// SYNC_ESCAPE_LEN is the length, in bits, of SYNC_ESCAPE
+pub const SYNC_ESCAPE_LEN: u32 = 36;
+
+/// SYNC_MAGIC starts every sync point, it is written at a byte boundary after SYNC_ESCAPE so a
+/// decoder can scan a damaged stream for it
+pub const SYNC_MAGIC: u64 = 0x5453_5a53;
+
+/// Framing
+///
+/// Framing determines how a decoder finds the end of a stream written by `StdEncoder`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Framing {
+    /// The stream is terminated by END_MARKER.
+    EndMarker,
+
+    /// The timestamp header is followed by 64 bits holding the number of `DataPoint`s and 64 bits
+    /// holding the total length of the stream in bits, and there is no END_MARKER. The decoder
+    /// stops after the given number of `DataPoint`s and checks it has read exactly the given
+    /// number of bits.
+    Counted,
+}
+
+/// ErrorBound
+///
+/// ErrorBound is the largest error allowed between a value passed to `StdEncoder` and the value
+/// decoded. Values are rounded to a multiple of the largest power of two which keeps them within
+/// the bound, so the low bits of consecutive values match and their XOR compresses better.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorBound {
+    /// The decoded value is within the given distance of the original value.
+    Absolute(u64),
+
+    /// The decoded value is within the given fraction, at least zero and less than one, of the
+    /// magnitude of the original value.
+    Relative(f64),
+}
+
+impl ErrorBound {
+    /// quantise returns the value closest to a multiple of a power of two which is within the
+    /// bound of `value`. If rounding would overflow `value` is returned unchanged
+    pub fn quantise(&self, value: i64) -> i64 {
+        let bound = match *self {
+            ErrorBound::Absolute(bound) => bound,
+            ErrorBound::Relative(fraction) => (fraction * value.unsigned_abs() as f64) as u64,
+        };
+        if bound == 0 {
+            return value;
+        }
+
+        // rounding to the nearest multiple of 2^k is off by at most 2^(k-1), which is within the
+        // bound for the largest k where 2^(k-1) <= bound
+        let step = 1i128 << (64 - bound.leading_zeros());
+        let quantised = (value as i128 + step / 2).div_euclid(step) * step;
+        if quantised < i64::MIN as i128 || quantised > i64::MAX as i128 {
+            return value;
+        }
+
+        // converting the relative bound to an integer can round it up for the largest values
+        match *self {
+            ErrorBound::Relative(fraction)
+                if (quantised - value as i128).unsigned_abs() as f64 >
+                   fraction * value.unsigned_abs() as f64 => value,
+            _ => quantised as i64,
+        }
+    }
+}
+
 /// StdEncoder
 ///
 /// StdEncoder is used to encode `DataPoint`s
-#[derive(Debug)]
-pub struct StdEncoder<T: Write, P: Predictor> {
+#[derive(Debug, Clone)]
+pub struct StdEncoder<T: Write, P: Predictor, Q: TimestampPredictor = DeltaOfDeltaPredictor> {
     time: u64, // current time
-    delta: u64, // current time delta
+    time_predictor: Q, // predicts the next time delta
     predictor: P, // current float value as bits
 
     // store the number of leading and trailing zeros in the current xor as u32 so we
@@ -32,6 +107,12 @@ pub struct StdEncoder<T: Write, P: Predictor> {
 
     first: bool, // will next DataPoint be the first DataPoint encoded
 
+    framing: Framing,
+    count: u64, // number of DataPoints encoded
+    checksum: bool, // append a checksum trailer when closed
+    sync_interval: u64, // number of DataPoints between sync points, 0 disables them
+    error_bound: Option<ErrorBound>, // values are quantised to within the bound before encoding
+
     w: T,
 }
 
@@ -42,13 +123,30 @@ impl<T, P> StdEncoder<T, P>
     /// new creates a new StdEncoder whose starting timestamp is `start` and writes its encoded
     /// bytes to `w`
     pub fn new(start: u64, w: T, p: P) -> Self {
+        StdEncoder::with_timestamp_predictor(start, w, p, DeltaOfDeltaPredictor::new())
+    }
+}
+
+impl<T, P, Q> StdEncoder<T, P, Q>
+    where T: Write,
+    P: Predictor,
+    Q: TimestampPredictor,
+{
+    /// with_timestamp_predictor creates a new StdEncoder whose starting timestamp is `start`,
+    /// which writes its encoded bytes to `w` and uses `q` to predict the delta between timestamps
+    pub fn with_timestamp_predictor(start: u64, w: T, p: P, q: Q) -> Self {
         let mut e = StdEncoder {
             time: start,
-            delta: 0,
+            time_predictor: q,
             predictor: p,
             leading_zeros: 64, // 64 is an initial sentinel value
             //trailing_zeros: 64, // 64 is an intitial sentinel value
             first: true,
+            framing: Framing::EndMarker,
+            count: 0,
+            checksum: false,
+            sync_interval: 0,
+            error_bound: None,
             w: w,
         };
 
@@ -58,9 +156,55 @@ impl<T, P> StdEncoder<T, P>
         e
     }
 
+    /// framing sets how the end of the stream is marked, it must be called before any
+    /// `DataPoint`s are encoded
+    pub fn framing(mut self, framing: Framing) -> Self {
+        assert!(self.first && self.framing == Framing::EndMarker,
+                "framing must be set once, before encoding any DataPoints");
+
+        if framing == Framing::Counted {
+            // reserve space for the number of DataPoints and the stream length, they are filled
+            // in when the encoder is closed
+            self.w.write_bits(0, 64);
+            self.w.write_bits(0, 64);
+        }
+
+        self.framing = framing;
+        self
+    }
+
+    /// checksum sets whether a 32 bit CRC32C of the stream is appended when the encoder is
+    /// closed, the checksum starts at the first byte boundary after the stream
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// sync_interval sets the encoder to write a sync point every `interval` `DataPoint`s, a value
+    /// of 0 disables sync points. A sync point is byte aligned and stores its `DataPoint` exactly,
+    /// along with its index in the stream, and resets both predictors so a decoder can resume
+    /// decoding from it after part of the stream has been damaged
+    pub fn sync_interval(mut self, interval: u64) -> Self {
+        self.sync_interval = interval;
+        self
+    }
+
+    /// error_bound sets the encoder to quantise values to within `error_bound` before they are
+    /// predicted and encoded, trading precision for a smaller stream. Decoding is unchanged
+    pub fn error_bound(mut self, error_bound: ErrorBound) -> Self {
+        if let ErrorBound::Relative(fraction) = error_bound {
+            assert!((0.0..1.0).contains(&fraction),
+                    "relative error bound must be at least zero and less than one");
+        }
+
+        self.error_bound = Some(error_bound);
+        self
+    }
+
     fn write_first(&mut self, time: u64, value_bits: u64) {
-        self.delta = time - self.time;
+        let delta = time - self.time;
         self.time = time;
+        self.time_predictor.update(delta);
         self.predictor.update(value_bits);
 
         // write one control bit so we can distinguish a stream which contains only an initial
@@ -69,7 +213,7 @@ impl<T, P> StdEncoder<T, P>
 
         // store the first delta with 14 bits which is enough to span just over 4 hours
         // if one wanted to use a window larger than 4 hours this size would increase
-        self.w.write_bits(self.delta, 14);
+        self.w.write_bits(delta, 14);
 
         // store the first value exactly
         println!("{}\t-> frist = {}", value_bits, value_bits);
@@ -78,91 +222,64 @@ impl<T, P> StdEncoder<T, P>
         self.first = true
     }
 
+    fn write_sync_point(&mut self, index: u64, time: u64, value_bits: u64) {
+        // the escape is followed by padding so that the rest of the sync point is byte aligned
+        self.w.write_bits(SYNC_ESCAPE, SYNC_ESCAPE_LEN);
+        let padding = (8 - self.w.bits_written() % 8) % 8;
+        self.w.write_bits(0, padding as u32);
+
+        self.w.write_bits(SYNC_MAGIC, 32);
+        self.w.write_bits(index, 64);
+        self.w.write_bits(time, 64);
+        self.w.write_bits(value_bits, 64);
+
+        // start predicting from scratch so nothing before the sync point is needed to decode what
+        // follows it
+        self.time = time;
+        self.time_predictor.reset();
+        self.predictor.reset();
+        self.predictor.update(value_bits);
+        self.leading_zeros = 64;
+    }
+
+    // is_sync_escape returns true if the delta of delta for `time` would be written as SYNC_ESCAPE
+    fn is_sync_escape(&self, time: u64) -> bool {
+        let delta = time - self.time;
+        delta.wrapping_sub(self.time_predictor.predict_next()) as i32 == i32::MIN
+    }
+
     fn write_next_timestamp(&mut self, time: u64) {
         let delta = time - self.time; // current delta
-        let dod = delta.wrapping_sub(self.delta) as i32; // delta of delta
+
+        // the difference between the actual and predicted delta, with the default predictor
+        // this is the delta of delta
+        let dod = delta.wrapping_sub(self.time_predictor.predict_next()) as i32;
 
         // store the delta of delta using variable length encoding
-        match dod {
-            0 => {
-                self.w.write_bit(Bit::Zero);
-            }
-            -63...64 => {
-                self.w.write_bits(0b10, 2);
-                self.w.write_bits(dod as u64, 7);
-            }
-            -255...256 => {
-                self.w.write_bits(0b110, 3);
-                self.w.write_bits(dod as u64, 9);
-            }
-            -2047...2048 => {
-                self.w.write_bits(0b1110, 4);
-                self.w.write_bits(dod as u64, 12);
-            }
-            _ => {
-                self.w.write_bits(0b1111, 4);
-                self.w.write_bits(dod as u64, 32);
-            }
-        }
+        encode::write_delta_of_delta(&mut self.w, dod);
 
-        self.delta = delta;
+        self.time_predictor.update(delta);
         self.time = time;
     }
 
     fn write_next_value(&mut self, value_bits: u64) {
-        let predicted_bits = self.predictor.predict_next();
-        let xor = value_bits ^ predicted_bits;
+        let xor = value_bits ^ self.predictor.predict_next();
         self.predictor.update(value_bits);
-            println!("{}\t-> xor = {}", value_bits, xor);
-
-        if xor == 0 {
-            // if xor with previous value is zero just store single zero bit
-            self.w.write_bit(Bit::Zero);
-            println!("{}\t-> Bit::Zero = {}", value_bits, predicted_bits);
-        } else {
-            self.w.write_bit(Bit::One);
-
-            let leading_zeros = xor.leading_zeros();
-            //let trailing_zeros = xor.trailing_zeros();
-
-            if leading_zeros == self.leading_zeros {//&& trailing_zeros == self.trailing_zeros {
-                // if the number of leading and trailing zeros in this xor are >= the leading and
-                // trailing zeros in the previous xor then we only need to store a control bit and
-                // the significant digits of this xor
-                let significant_digits = 64 - self.leading_zeros;// - self.trailing_zeros;
-                println!("{}\t-> significant_digits unchanged {})", value_bits, significant_digits);
-                self.w.write_bit(Bit::Zero);
-                self.w.write_bits(xor/* .wrapping_shr(self.trailing_zeros) */, significant_digits);
-            } else {
-
-                // if the number of leading and trailing zeros in this xor are not less than the
-                // leading and trailing zeros in the previous xor then we store a control bit and
-                // use 6 bits to store the number of leading zeros and 6 bits to store the number
-                // of significant digits before storing the significant digits themselves
-
-                self.w.write_bit(Bit::One);
-
-                // if significant_digits is 64 we cannot encode it using 6 bits, however since
-                // significant_digits is guaranteed to be at least 1 we can subtract 1 to ensure
-                // significant_digits can always be expressed with 6 bits or less
-                let significant_digits = 64 - leading_zeros;// - trailing_zeros;
-                println!("{}\t-> significant_digits changed = 64 - {} = {}", value_bits, leading_zeros, significant_digits);
-                self.w.write_bits(leading_zeros as u64, 6);
-                self.w.write_bits(xor/* .wrapping_shr(trailing_zeros) */, significant_digits);
-
-                // finally we need to update the number of leading and trailing zeros
-                self.leading_zeros = leading_zeros;
-                //self.trailing_zeros = trailing_zeros;
-            }
-        }
+
+        // store the xor with the predicted value, along with its number of leading zeros when
+        // that changes
+        encode::write_xor(&mut self.w, xor, &mut self.leading_zeros);
     }
 }
 
-impl<T, P> Encode for StdEncoder<T, P>
-    where T: Write, P: Predictor
+impl<T, P, Q> Encode for StdEncoder<T, P, Q>
+    where T: Write, P: Predictor, Q: TimestampPredictor
 {
     fn encode(&mut self, dp: DataPoint) {
-        let value_bits = unsafe { mem::transmute::<i64, u64>(dp.value) };
+        let value = self.error_bound.map_or(dp.value, |bound| bound.quantise(dp.value));
+        let value_bits = unsafe { mem::transmute::<i64, u64>(value) };
+        let index = self.count;
+        self.count += 1;
 
         if self.first {
             self.write_first(dp.time, value_bits);
@@ -170,13 +287,39 @@ impl<T, P> Encode for StdEncoder<T, P>
             return;
         }
 
+        let sync = self.sync_interval > 0 && index.is_multiple_of(self.sync_interval);
+        if sync || self.is_sync_escape(dp.time) {
+            self.write_sync_point(index, dp.time, value_bits);
+            return;
+        }
+
         self.write_next_timestamp(dp.time);
         self.write_next_value(value_bits)
     }
 
     fn close(mut self) -> Box<[u8]> {
-        self.w.write_bits(END_MARKER, 36);
-        self.w.close()
+        let bytes = match self.framing {
+            Framing::EndMarker => {
+                self.w.write_bits(END_MARKER, 36);
+                self.w.close()
+            }
+            Framing::Counted => {
+                let count = self.count;
+                let len = self.w.bits_written();
+
+                // the counts follow the 64 bit timestamp header
+                let mut bytes = self.w.close();
+                bytes[8..16].copy_from_slice(&count.to_be_bytes());
+                bytes[16..24].copy_from_slice(&len.to_be_bytes());
+                bytes
+            }
+        };
+
+        if self.checksum {
+            return checksum::append_checksum(bytes);
+        }
+
+        bytes
     }
 }
 
@@ -185,8 +328,9 @@ mod tests {
     use DataPoint;
     use encode::Encode;
     use stream::BufferedWriter;
-    use super::StdEncoder;
+    use super::{StdEncoder, Framing, ErrorBound};
     use predictor::SimplePredictor;
+    use checksum;
 
     #[test]
     fn create_new_encoder() {
@@ -250,4 +394,75 @@ mod tests {
 
         assert_eq!(bytes[..], expected_bytes[..]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn encode_counted_datapoint() {
+        let w = BufferedWriter::new();
+        let p = SimplePredictor::new();
+        let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
+        let mut e = StdEncoder::new(start_time, w, p).framing(Framing::Counted);
+
+        e.encode(DataPoint::new(1482268055 + 10, 124));
+
+        // 192 bits of header, 1 control bit, 14 bits of delta and 64 bits of value
+        let bytes = e.close();
+        let expected_bytes: [u8; 34] = [0, 0, 0, 0, 88, 89, 157, 151, 0, 0, 0, 0, 0, 0, 0, 1, 0,
+                                        0, 0, 0, 0, 0, 1, 15, 0, 20, 0, 0, 0, 0, 0, 0, 0, 248];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+
+    #[test]
+    fn encode_sync_point() {
+        let w = BufferedWriter::new();
+        let p = SimplePredictor::new();
+        let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
+        let mut e = StdEncoder::new(start_time, w, p).sync_interval(1);
+
+        e.encode(DataPoint::new(1482268055 + 10, 124));
+        e.encode(DataPoint::new(1482268055 + 20, 125));
+
+        // the second DataPoint is written as SYNC_ESCAPE, padding to the next byte boundary,
+        // SYNC_MAGIC, its index, its timestamp and its value
+        let bytes = e.close();
+        let expected_bytes: [u8; 56] = [0, 0, 0, 0, 88, 89, 157, 151, 0, 20, 0, 0, 0, 0, 0, 0, 0,
+                                        249, 240, 0, 0, 0, 0, 84, 83, 90, 83, 0, 0, 0, 0, 0, 0, 0,
+                                        1, 0, 0, 0, 0, 88, 89, 157, 171, 0, 0, 0, 0, 0, 0, 0, 125,
+                                        240, 0, 0, 0, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+
+    #[test]
+    fn encode_checksummed_datapoint() {
+        let w = BufferedWriter::new();
+        let p = SimplePredictor::new();
+        let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
+        let mut e = StdEncoder::new(start_time, w, p).checksum(true);
+
+        e.encode(DataPoint::new(1482268055 + 10, 124));
+
+        let bytes = e.close();
+        assert_eq!(bytes.len(), 23 + 4);
+        assert!(checksum::verify(&bytes));
+    }
+
+    #[test]
+    fn quantise_values() {
+        let bound = ErrorBound::Absolute(5);
+        assert_eq!(bound.quantise(0), 0);
+        assert_eq!(bound.quantise(3), 0);
+        assert_eq!(bound.quantise(4), 8);
+        assert_eq!(bound.quantise(100), 104);
+        assert_eq!(bound.quantise(-100), -96);
+        assert_eq!(bound.quantise(i64::MAX), i64::MAX);
+        assert_eq!(ErrorBound::Absolute(0).quantise(12345), 12345);
+
+        let bound = ErrorBound::Relative(0.01);
+        assert_eq!(bound.quantise(50), 50);
+        assert_eq!(bound.quantise(1000), 1008);
+        assert_eq!(bound.quantise(1001), 1008);
+        assert_eq!(bound.quantise(-123456), -122880);
+        assert_eq!(bound.quantise(-123457), -122880);
+    }
+}
@@ -151,25 +151,61 @@ impl DataPoint {
 
 pub mod stream;
 
+pub mod checksum;
+
 pub mod predictor;
 pub use self::predictor::Predictor;
 pub use self::predictor::{SimplePredictor, FcmPredictor, DfcmPredictor};
+pub use self::predictor::TimestampPredictor;
+pub use self::predictor::{DeltaOfDeltaPredictor, FcmDeltaPredictor};
 
 pub mod encode;
 pub use self::encode::Encode;
 pub use self::encode::std_encoder::StdEncoder;
+pub use self::encode::prometheus_encoder::PrometheusEncoder;
+pub use self::encode::gorilla_encoder::GorillaEncoder;
+pub use self::encode::m3tsz_encoder::M3TszEncoder;
+pub use self::encode::multi_encoder::MultiEncoder;
+pub use self::encode::state_encoder::StateEncoder;
+pub use self::encode::dictionary_encoder::DictionaryEncoder;
 
 pub mod decode;
 pub use self::decode::Decode;
 pub use self::decode::std_decoder::StdDecoder;
+pub use self::decode::prometheus_decoder::PrometheusDecoder;
+pub use self::decode::gorilla_decoder::GorillaDecoder;
+pub use self::decode::m3tsz_decoder::M3TszDecoder;
+pub use self::decode::multi_decoder::MultiDecoder;
+pub use self::decode::state_decoder::StateDecoder;
+pub use self::decode::dictionary_decoder::DictionaryDecoder;
+
+pub mod store;
+pub use self::store::Store;
+pub use self::store::sharded::ShardedStore;
+
+pub mod downsample;
+
+pub mod aggregate;
+
+pub mod rate;
+
+pub mod merge;
+
+pub mod resample;
+
+#[cfg(test)]
+mod test_util;
 
 #[cfg(test)]
 mod tests {
     use std::vec::Vec;
 
     use super::{DataPoint, Encode, Decode, StdEncoder, StdDecoder, SimplePredictor};
+    use super::{TimestampPredictor, DeltaOfDeltaPredictor, FcmDeltaPredictor};
     use super::stream::{BufferedReader, BufferedWriter};
-    use super::decode::Error;
+    use super::decode::{self, Error};
+    use super::encode::std_encoder::{ErrorBound, Framing, SYNC_MAGIC};
+    use super::test_util::xorshift;
 
     const DATA: &'static str = "1482892270,176
 1482892280,778
@@ -236,4 +272,329 @@ mod tests {
 
         assert_eq!(original_datapoints, new_datapoints);
     }
-}
\ No newline at end of file
+
+    fn encode_with<Q: TimestampPredictor>(start: u64, dps: &[DataPoint], q: Q) -> Box<[u8]> {
+        let w = BufferedWriter::new();
+        let p = SimplePredictor::new();
+        let mut encoder = StdEncoder::with_timestamp_predictor(start, w, p, q);
+
+        for dp in dps {
+            encoder.encode(*dp);
+        }
+
+        encoder.close()
+    }
+
+    fn decode_with<Q: TimestampPredictor>(bytes: Box<[u8]>, q: Q) -> Vec<DataPoint> {
+        let r = BufferedReader::new(bytes);
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::with_timestamp_predictor(r, p, q);
+
+        decode::decode_all(&mut decoder).expect("Received an error from decoder")
+    }
+
+    #[test]
+    fn timestamp_predictor_round_trip() {
+        // a cron schedule which fires at 0, 15 and 45 minutes past every hour
+        let start = 1482892200;
+        let mut original_datapoints = Vec::new();
+        for hour in 0..12 {
+            for minute in &[0, 15, 45] {
+                let t = start + hour * 3600 + minute * 60 + 60;
+                original_datapoints.push(DataPoint::new(t, 1));
+            }
+        }
+
+        let dod_bytes = encode_with(start, &original_datapoints, DeltaOfDeltaPredictor::new());
+        let fcm_bytes = encode_with(start, &original_datapoints, FcmDeltaPredictor::new(1024));
+
+        assert!(fcm_bytes.len() < dod_bytes.len());
+        assert_eq!(decode_with(dod_bytes, DeltaOfDeltaPredictor::new()),
+                   original_datapoints);
+        assert_eq!(decode_with(fcm_bytes, FcmDeltaPredictor::new(1024)),
+                   original_datapoints);
+    }
+
+    #[test]
+    fn large_delta_of_delta_round_trip() {
+        let start = 1482892200;
+        let original_datapoints = vec![DataPoint::new(start + 10, 1),
+                                       DataPoint::new(start + 20, 2),
+                                       DataPoint::new(start + 100020, 3),
+                                       DataPoint::new(start + 100030, 4)];
+
+        let bytes = encode_with(start, &original_datapoints, DeltaOfDeltaPredictor::new());
+        assert_eq!(decode_with(bytes, DeltaOfDeltaPredictor::new()), original_datapoints);
+    }
+
+    #[test]
+    fn counted_framing_round_trip() {
+        let start = 1482892200;
+        let original_datapoints: Vec<DataPoint> = (0..100)
+            .map(|i| DataPoint::new(start + i * 10 + i % 7, (i * i) as i64))
+            .collect();
+
+        let w = BufferedWriter::new();
+        let p = SimplePredictor::new();
+        let mut encoder = StdEncoder::new(start, w, p).framing(Framing::Counted);
+        for dp in &original_datapoints {
+            encoder.encode(*dp);
+        }
+
+        let r = BufferedReader::new(encoder.close());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p).framing(Framing::Counted);
+
+        let mut new_datapoints = Vec::new();
+        loop {
+            match decoder.next() {
+                Ok(dp) => new_datapoints.push(dp),
+                Err(Error::EndOfStream) => break,
+                Err(err) => panic!("Received an error from decoder: {:?}", err),
+            }
+        }
+
+        assert_eq!(original_datapoints, new_datapoints);
+    }
+
+    #[test]
+    fn checksum_detects_flipped_bits() {
+        let start = 1482892200;
+        let original_datapoints: Vec<DataPoint> = (0..20)
+            .map(|i| DataPoint::new(start + i * 10 + i % 3, (i * 7) as i64))
+            .collect();
+
+        let w = BufferedWriter::new();
+        let p = SimplePredictor::new();
+        let mut encoder = StdEncoder::new(start, w, p).checksum(true);
+        for dp in &original_datapoints {
+            encoder.encode(*dp);
+        }
+        let bytes = encoder.close();
+
+        // a single flipped bit must never decode to a clean stream
+        for i in 0..bytes.len() * 8 {
+            let mut corrupted = bytes.to_vec();
+            corrupted[i / 8] ^= 1 << (i % 8);
+
+            let r = BufferedReader::new(corrupted.into_boxed_slice());
+            let p = SimplePredictor::new();
+            let mut decoder = StdDecoder::new(r, p).checksum(true);
+
+            let clean = loop {
+                match decoder.next() {
+                    Ok(_) => {}
+                    Err(Error::EndOfStream) => break true,
+                    Err(_) => break false,
+                }
+            };
+
+            assert!(!clean, "flipping bit {} was not detected", i);
+        }
+    }
+
+    fn encode_with_sync_points(start: u64, dps: &[DataPoint], interval: u64) -> Box<[u8]> {
+        let w = BufferedWriter::new();
+        let p = SimplePredictor::new();
+        let mut encoder = StdEncoder::new(start, w, p).sync_interval(interval);
+
+        for dp in dps {
+            encoder.encode(*dp);
+        }
+
+        encoder.close()
+    }
+
+    // find_sync_point returns the offset of the sync point for the DataPoint at `index`
+    fn find_sync_point(bytes: &[u8], index: u64) -> usize {
+        let mut pattern = (SYNC_MAGIC as u32).to_be_bytes().to_vec();
+        pattern.extend_from_slice(&index.to_be_bytes());
+        bytes.windows(12).position(|w| w == &pattern[..]).unwrap()
+    }
+
+    #[test]
+    fn sync_points_round_trip() {
+        let start = 1482892200;
+        let original_datapoints: Vec<DataPoint> = (0..100)
+            .map(|i| DataPoint::new(start + i * 10 + i % 7, (i * i) as i64))
+            .collect();
+
+        let bytes = encode_with_sync_points(start, &original_datapoints, 10);
+        let new_datapoints = decode_with(bytes, DeltaOfDeltaPredictor::new());
+
+        assert_eq!(original_datapoints, new_datapoints);
+    }
+
+    #[test]
+    fn sync_escape_round_trip() {
+        // the third DataPoint has a delta of delta of 2^31 which would be written as SYNC_ESCAPE,
+        // so the encoder has to write a sync point instead
+        let start = 1482892200;
+        let original_datapoints = vec![DataPoint::new(start + 10, 1),
+                                       DataPoint::new(start + 20, 2),
+                                       DataPoint::new(start + 30 + (1 << 31), 3),
+                                       DataPoint::new(start + 40 + (1 << 31), 4)];
+
+        let bytes = encode_with(start, &original_datapoints, DeltaOfDeltaPredictor::new());
+        let new_datapoints = decode_with(bytes, DeltaOfDeltaPredictor::new());
+
+        assert_eq!(original_datapoints, new_datapoints);
+    }
+
+    #[test]
+    fn recover_from_damaged_sync_point() {
+        let start = 1482892200;
+        let original_datapoints: Vec<DataPoint> = (0..100)
+            .map(|i| DataPoint::new(start + i * 10 + i % 7, (i * i) as i64))
+            .collect();
+
+        let mut bytes = encode_with_sync_points(start, &original_datapoints, 10).into_vec();
+        let offset = find_sync_point(&bytes, 30);
+        bytes[offset] ^= 0xff;
+
+        let r = BufferedReader::new(bytes.clone().into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p);
+        for dp in &original_datapoints[..30] {
+            assert_eq!(decoder.next().unwrap(), *dp);
+        }
+        assert_eq!(*decoder.next().err().unwrap().kind(), Error::InvalidSyncPoint);
+
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p).recover(true);
+        for dp in &original_datapoints[..30] {
+            assert_eq!(decoder.next().unwrap(), *dp);
+        }
+        assert_eq!(decoder.next().err().unwrap(), Error::PointsLost(30, 40));
+        for dp in &original_datapoints[40..] {
+            assert_eq!(decoder.next().unwrap(), *dp);
+        }
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn recover_without_sync_point() {
+        let start = 1482892200;
+        let original_datapoints: Vec<DataPoint> = (0..25)
+            .map(|i| DataPoint::new(start + i * 10, i as i64))
+            .collect();
+
+        // once the last sync point is damaged there is nothing left to resync to
+        let mut bytes = encode_with_sync_points(start, &original_datapoints, 10).into_vec();
+        let offset = find_sync_point(&bytes, 20);
+        bytes[offset] ^= 0xff;
+
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p).recover(true);
+        for dp in &original_datapoints[..20] {
+            assert_eq!(decoder.next().unwrap(), *dp);
+        }
+        assert_eq!(*decoder.next().err().unwrap().kind(), Error::InvalidSyncPoint);
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn error_position_after_datapoints() {
+        let start = 1482892200;
+        let original_datapoints: Vec<DataPoint> = (0..10)
+            .map(|i| DataPoint::new(start + i * 10, i as i64))
+            .collect();
+
+        let mut bytes = encode_with(start, &original_datapoints, DeltaOfDeltaPredictor::new())
+            .into_vec();
+        let len = bytes.len();
+        bytes.truncate(len - 6);
+
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let p = SimplePredictor::new();
+        let mut decoder = StdDecoder::new(r, p);
+        let mut last = None;
+        let err = loop {
+            match decoder.next() {
+                Ok(dp) => last = Some(dp),
+                Err(err) => break err,
+            }
+        };
+
+        let last = last.unwrap();
+        let position = err.position().unwrap();
+        assert!(err.is_truncated());
+        assert_eq!(position.last_time, Some(last.time));
+        assert_eq!(original_datapoints[position.index as usize - 1], last);
+    }
+
+    #[test]
+    fn error_bound_holds() {
+        let bounds = [ErrorBound::Absolute(0),
+                      ErrorBound::Absolute(1),
+                      ErrorBound::Absolute(1000),
+                      ErrorBound::Absolute(u64::MAX),
+                      ErrorBound::Relative(0.0),
+                      ErrorBound::Relative(0.001),
+                      ErrorBound::Relative(0.5)];
+
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for bound in &bounds {
+            // values of every magnitude, including the extremes
+            let mut dps = vec![DataPoint::new(1482892260, i64::MAX),
+                               DataPoint::new(1482892270, i64::MIN)];
+            for i in 0..2000 {
+                let random = xorshift(&mut state);
+                let value = (random >> (xorshift(&mut state) % 64)) as i64;
+                dps.push(DataPoint::new(1482892280 + i * 10, value));
+            }
+
+            let w = BufferedWriter::new();
+            let mut encoder = StdEncoder::new(1482892260, w, SimplePredictor::new())
+                .error_bound(*bound);
+            for dp in &dps {
+                encoder.encode(*dp);
+            }
+            let decoded = decode_with(encoder.close(), DeltaOfDeltaPredictor::new());
+
+            assert_eq!(decoded.len(), dps.len());
+            for (original, decoded) in dps.iter().zip(&decoded) {
+                assert_eq!(original.time, decoded.time);
+
+                let error = (original.value as i128 - decoded.value as i128).unsigned_abs();
+                let within = match *bound {
+                    ErrorBound::Absolute(bound) => error <= bound as u128,
+                    ErrorBound::Relative(fraction) => {
+                        error as f64 <= fraction * original.value.unsigned_abs() as f64
+                    }
+                };
+                assert!(within,
+                        "{} decoded as {} is not within {:?}",
+                        original.value,
+                        decoded.value,
+                        bound);
+            }
+        }
+    }
+
+    #[test]
+    fn error_bound_compresses_noise() {
+        // a slowly rising signal with a little noise
+        let mut state = 0x9e37_79b9_7f4a_7c15;
+        let dps: Vec<DataPoint> = (0..1000)
+            .map(|i| DataPoint::new(1482892270 + i * 10, 100_000 + i as i64 * 50 +
+                                                         (xorshift(&mut state) % 64) as i64))
+            .collect();
+
+        let encode = |bound: Option<ErrorBound>| {
+            let w = BufferedWriter::new();
+            let mut encoder = StdEncoder::new(1482892260, w, SimplePredictor::new());
+            if let Some(bound) = bound {
+                encoder = encoder.error_bound(bound);
+            }
+            for dp in &dps {
+                encoder.encode(*dp);
+            }
+            encoder.close().len()
+        };
+
+        assert!(encode(Some(ErrorBound::Absolute(256))) < encode(None) * 3 / 4);
+    }
+}
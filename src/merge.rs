@@ -0,0 +1,236 @@
+use std::{error, fmt};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+
+use {DataPoint, Decode};
+use decode;
+
+/// Error
+///
+/// Error is an error returned by one of the sources of a `Merge`, along with the index of the
+/// source.
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    pub source: usize,
+    pub err: decode::Error,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Source {}: {}", self.source, self.err)
+    }
+}
+
+impl error::Error for Error {
+    #[allow(deprecated)]
+    fn description(&self) -> &str {
+        self.err.description()
+    }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.err)
+    }
+}
+
+/// Merge
+///
+/// Merge is an iterator which merges the `DataPoint`s of several decoders, such as the blocks of
+/// one series or several series, into a single stream in order of time. Each `DataPoint` is
+/// tagged with the index of the decoder it came from, and `DataPoint`s with equal timestamps are
+/// returned in order of their decoders. A decoder which returns an error is not read from again.
+#[derive(Debug)]
+pub struct Merge<D: Decode> {
+    sources: Vec<D>,
+    heap: BinaryHeap<Reverse<(u64, usize, i64)>>, // next DataPoint of each source
+    pending: Vec<usize>, // sources which need to be read before the heap is complete
+}
+
+impl<D> Merge<D>
+    where D: Decode
+{
+    /// new creates a new Merge of the `DataPoint`s decoded by `sources`, which must each return
+    /// their `DataPoint`s in order of time
+    pub fn new(sources: Vec<D>) -> Self {
+        let pending = (0..sources.len()).rev().collect();
+        Merge {
+            sources,
+            heap: BinaryHeap::new(),
+            pending,
+        }
+    }
+
+    /// join turns the merge into a `Join`, which groups the `DataPoint`s with equal timestamps
+    pub fn join(self) -> Join<D> {
+        Join {
+            merge: self,
+            peeked: None,
+            errors: VecDeque::new(),
+        }
+    }
+
+    /// into_sources returns the decoders being merged
+    pub fn into_sources(self) -> Vec<D> {
+        self.sources
+    }
+}
+
+impl<D> Iterator for Merge<D>
+    where D: Decode
+{
+    type Item = Result<(usize, DataPoint), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(source) = self.pending.pop() {
+            match decode::next_datapoint(&mut self.sources[source]) {
+                Ok(Some(dp)) => self.heap.push(Reverse((dp.time, source, dp.value))),
+                Ok(None) => {}
+                Err(err) => return Some(Err(Error { source, err })),
+            }
+        }
+
+        let Reverse((time, source, value)) = self.heap.pop()?;
+        self.pending.push(source);
+        Some(Ok((source, DataPoint::new(time, value))))
+    }
+}
+
+/// Join
+///
+/// Join is an iterator which aligns the `DataPoint`s of several decoders by timestamp. Each item
+/// is a timestamp along with the value of every decoder at that time, or None for decoders which
+/// have no `DataPoint` at that time. If a decoder has more than one `DataPoint` with the same
+/// timestamp, each one is returned in its own item. An error from a decoder is returned after
+/// the item which was being collected when it occurred.
+#[derive(Debug)]
+pub struct Join<D: Decode> {
+    merge: Merge<D>,
+    peeked: Option<(usize, DataPoint)>, // first DataPoint of the next row
+    errors: VecDeque<Error>, // errors to return before the next row
+}
+
+impl<D> Iterator for Join<D>
+    where D: Decode
+{
+    type Item = Result<(u64, Vec<Option<i64>>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.errors.pop_front() {
+            return Some(Err(err));
+        }
+
+        let (source, dp) = match self.peeked.take().map(Ok).or_else(|| self.merge.next())? {
+            Ok(next) => next,
+            Err(err) => return Some(Err(err)),
+        };
+
+        let mut values = vec![None; self.merge.sources.len()];
+        values[source] = Some(dp.value);
+
+        // errors are held back until the row is complete, so the other sources' DataPoints at
+        // this timestamp still end up in the same row
+        for next in self.merge.by_ref() {
+            match next {
+                Ok((source, next)) if next.time == dp.time && values[source].is_none() => {
+                    values[source] = Some(next.value);
+                }
+                Ok(next) => {
+                    self.peeked = Some(next);
+                    break;
+                }
+                Err(err) => self.errors.push_back(err),
+            }
+        }
+
+        Some(Ok((dp.time, values)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {DataPoint, StdDecoder, SimplePredictor};
+    use stream::BufferedReader;
+    use test_util;
+    use super::{Error, Merge};
+
+    fn decoder(datapoints: &[(u64, i64)]) -> StdDecoder<BufferedReader, SimplePredictor> {
+        let datapoints: Vec<DataPoint> = datapoints.iter()
+            .map(|&(time, value)| DataPoint::new(time, value))
+            .collect();
+        test_util::decoder(&datapoints)
+    }
+
+    fn sources() -> Vec<StdDecoder<BufferedReader, SimplePredictor>> {
+        vec![decoder(&[(10, 1), (20, 2), (30, 3), (30, 4)]),
+             decoder(&[(15, 5), (20, 6), (40, 7)]),
+             decoder(&[(20, 8), (30, 9)])]
+    }
+
+    #[test]
+    fn merge_in_time_order() {
+        let merged: Vec<(usize, DataPoint)> = Merge::new(sources()).map(Result::unwrap).collect();
+        assert_eq!(merged,
+                   vec![(0, DataPoint::new(10, 1)),
+                        (1, DataPoint::new(15, 5)),
+                        (0, DataPoint::new(20, 2)),
+                        (1, DataPoint::new(20, 6)),
+                        (2, DataPoint::new(20, 8)),
+                        (0, DataPoint::new(30, 3)),
+                        (0, DataPoint::new(30, 4)),
+                        (2, DataPoint::new(30, 9)),
+                        (1, DataPoint::new(40, 7))]);
+    }
+
+    #[test]
+    fn join_equal_timestamps() {
+        let joined: Vec<(u64, Vec<Option<i64>>)> = Merge::new(sources())
+            .join()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(joined,
+                   vec![(10, vec![Some(1), None, None]),
+                        (15, vec![None, Some(5), None]),
+                        (20, vec![Some(2), Some(6), Some(8)]),
+                        (30, vec![Some(3), None, None]),
+                        (30, vec![Some(4), None, Some(9)]),
+                        (40, vec![None, Some(7), None])]);
+    }
+
+    // broken returns a decoder of a stream holding (10, 1) and (20, 2) which is cut off part way
+    // through its second DataPoint
+    fn broken() -> StdDecoder<BufferedReader, SimplePredictor> {
+        let mut truncated = test_util::encode(&[DataPoint::new(10, 1), DataPoint::new(20, 2)])
+            .into_vec();
+        truncated.truncate(truncated.len() - 6);
+        StdDecoder::new(BufferedReader::new(truncated.into_boxed_slice()),
+                        SimplePredictor::new())
+    }
+
+    #[test]
+    fn merge_source_error() {
+        let merged: Vec<Result<(usize, DataPoint), Error>> =
+            Merge::new(vec![decoder(&[(5, 7), (25, 8)]), broken()]).collect();
+        assert_eq!(merged.len(), 4);
+        assert_eq!(merged[0], Ok((0, DataPoint::new(5, 7))));
+        assert_eq!(merged[1], Ok((1, DataPoint::new(10, 1))));
+        match merged[2] {
+            Err(Error { source: 1, ref err }) => assert!(err.is_truncated(), "{:?}", err),
+            ref other => panic!("expected an error from source 1, got {:?}", other),
+        }
+        assert_eq!(merged[3], Ok((0, DataPoint::new(25, 8))));
+    }
+
+    #[test]
+    fn join_source_error() {
+        // the broken source fails while the row at 10 is being collected, the row still holds
+        // every source's value and the error follows it
+        let sources = vec![decoder(&[(10, 3), (20, 4)]), broken(), decoder(&[(10, 5), (20, 6)])];
+        let joined: Vec<_> = Merge::new(sources).join().collect();
+        assert_eq!(joined.len(), 3);
+        assert_eq!(joined[0], Ok((10, vec![Some(3), Some(1), Some(5)])));
+        match joined[1] {
+            Err(Error { source: 1, ref err }) => assert!(err.is_truncated(), "{:?}", err),
+            ref other => panic!("expected an error from source 1, got {:?}", other),
+        }
+        assert_eq!(joined[2], Ok((20, vec![Some(4), None, Some(6)])));
+    }
+}
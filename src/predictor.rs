@@ -3,8 +3,11 @@
 pub trait Predictor {
     fn predict_next(&self) -> u64;
     fn update(&mut self, value: u64);
+
+    fn reset(&mut self);
 }
 
+#[derive(Debug, Clone)]
 pub struct SimplePredictor {
     next_value:u64,
 }
@@ -22,8 +25,12 @@ impl Predictor for SimplePredictor {
     fn update(&mut self, value: u64) {
         self.next_value = value;
     }
+    fn reset(&mut self) {
+        self.next_value = 0;
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct FcmPredictor {
     table:Vec<u64>,
     last_hash:u64,
@@ -48,8 +55,15 @@ impl Predictor for FcmPredictor {
         self.table[self.last_hash as usize] = value;
         self.last_hash = ((self.last_hash << 5) ^ (value >> 50)) & self.mask;
     }
+    fn reset(&mut self) {
+        for v in self.table.iter_mut() {
+            *v = 0;
+        }
+        self.last_hash = 0;
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct DfcmPredictor {
     table: Vec<u64>,
     last_hash: u64,
@@ -77,4 +91,95 @@ impl Predictor for DfcmPredictor {
         self.last_hash = ((self.last_hash << 5) ^ ((value - self.last_value) >> 50)) & self.mask;
         self.last_value = value;
     }
-}
\ No newline at end of file
+    fn reset(&mut self) {
+        for v in self.table.iter_mut() {
+            *v = 0;
+        }
+        self.last_hash = 0;
+        self.last_value = 0;
+    }
+}
+
+/// TimestampPredictor
+///
+/// TimestampPredictor predicts the delta between the next timestamp and the current one. Encoders
+/// only store the difference between the actual delta and the predicted delta.
+pub trait TimestampPredictor {
+    fn predict_next(&self) -> u64;
+    fn update(&mut self, delta: u64);
+
+    fn reset(&mut self);
+}
+
+/// DeltaOfDeltaPredictor
+///
+/// DeltaOfDeltaPredictor predicts that the next delta will equal the previous delta, which gives
+/// the delta of delta encoding described in the Gorilla paper.
+#[derive(Debug, Clone)]
+pub struct DeltaOfDeltaPredictor {
+    delta: u64,
+}
+
+impl DeltaOfDeltaPredictor {
+    pub fn new() -> Self {
+        DeltaOfDeltaPredictor { delta: 0 }
+    }
+}
+
+impl Default for DeltaOfDeltaPredictor {
+    fn default() -> Self {
+        DeltaOfDeltaPredictor::new()
+    }
+}
+
+impl TimestampPredictor for DeltaOfDeltaPredictor {
+    fn predict_next(&self) -> u64 {
+        self.delta
+    }
+    fn update(&mut self, delta: u64) {
+        self.delta = delta;
+    }
+    fn reset(&mut self) {
+        self.delta = 0;
+    }
+}
+
+/// FcmDeltaPredictor
+///
+/// FcmDeltaPredictor uses a finite context method over the history of deltas, so that schedules
+/// that repeat an irregular pattern of deltas can still be predicted exactly.
+#[derive(Debug, Clone)]
+pub struct FcmDeltaPredictor {
+    table: Vec<u64>,
+    last_hash: u64,
+    mask: u64,
+}
+
+impl FcmDeltaPredictor {
+    /// new creates a new FcmDeltaPredictor, `size` must be a power of two
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two(), "size must be a power of two");
+
+        FcmDeltaPredictor {
+            table: vec![0; size],
+            last_hash: 0,
+            mask: (size - 1) as _,
+        }
+    }
+}
+
+impl TimestampPredictor for FcmDeltaPredictor {
+    fn predict_next(&self) -> u64 {
+        self.table[self.last_hash as usize]
+    }
+    fn update(&mut self, delta: u64) {
+        self.table[self.last_hash as usize] = delta;
+        self.last_hash = ((self.last_hash << 5) ^ delta) & self.mask;
+    }
+    fn reset(&mut self) {
+        for v in self.table.iter_mut() {
+            *v = 0;
+        }
+        self.last_hash = 0;
+    }
+}
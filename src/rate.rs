@@ -0,0 +1,211 @@
+use {DataPoint, Decode};
+use decode;
+
+/// Counter
+///
+/// Counter tracks a monotonically increasing counter within a window of time, as its
+/// `DataPoint`s are decoded, to calculate how much it increased. A value lower than the one before
+/// it is taken to be a reset of the counter, by a process restarting for example, and the value
+/// before the reset is added back on. `DataPoint`s must be added in order of time, so when a
+/// series is split into blocks they must be consumed oldest first.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Counter {
+    start: u64, // start of the window, inclusive
+    end: u64, // end of the window, exclusive
+    count: u64, // number of DataPoints in the window
+    first: Option<DataPoint>,
+    previous: Option<DataPoint>,
+    last: Option<DataPoint>,
+    correction: f64, // sum of the values just before each reset
+}
+
+impl Counter {
+    /// new creates a new Counter over the window between `start`, inclusive, and `end`, exclusive
+    pub fn new(start: u64, end: u64) -> Self {
+        assert!(start < end, "window must not be empty");
+
+        Counter {
+            start,
+            end,
+            count: 0,
+            first: None,
+            previous: None,
+            last: None,
+            correction: 0.0,
+        }
+    }
+
+    /// add adds `dp` to the counter if it falls within the window
+    pub fn add(&mut self, dp: DataPoint) {
+        if dp.time < self.start || dp.time >= self.end {
+            return;
+        }
+
+        if let Some(last) = self.last {
+            if dp.value < last.value {
+                self.correction += last.value as f64;
+            }
+        }
+
+        self.count += 1;
+        self.first = self.first.or(Some(dp));
+        self.previous = self.last;
+        self.last = Some(dp);
+    }
+
+    /// consume adds every `DataPoint` read from `decoder` until the end of its stream, or until
+    /// the first `DataPoint` at or after the end of the window
+    pub fn consume<D: Decode>(&mut self, decoder: &mut D) -> Result<(), decode::Error> {
+        let end = self.end;
+        decode::for_each(decoder, |dp| {
+            if dp.time < end {
+                self.add(dp);
+            }
+            dp.time < end
+        })
+    }
+
+    /// increase returns how much the counter increased over the whole window, extrapolating from
+    /// the first and last `DataPoint`s to the edges of the window as Prometheus does. Returns None
+    /// if there are fewer than two `DataPoint`s in the window
+    pub fn increase(&self) -> Option<f64> {
+        let (first, last) = match (self.first, self.last) {
+            (Some(first), Some(last)) if self.count >= 2 => (first, last),
+            _ => return None,
+        };
+
+        let increase = (last.value - first.value) as f64 + self.correction;
+        let sampled = (last.time - first.time) as f64;
+        if sampled == 0.0 {
+            return None;
+        }
+        let average = sampled / (self.count - 1) as f64;
+
+        let mut to_start = (first.time - self.start) as f64;
+        let to_end = (self.end - last.time) as f64;
+
+        // a counter can't go below zero, so don't extrapolate back past the point it would have
+        // been zero
+        if increase > 0.0 && first.value >= 0 {
+            to_start = to_start.min(sampled * first.value as f64 / increase);
+        }
+
+        // extrapolate all the way to an edge of the window if the series looks like it carries
+        // on to it, otherwise only by half the average interval between DataPoints
+        let threshold = average * 1.1;
+        let extrapolate = |to_edge: f64| if to_edge < threshold { to_edge } else { average / 2.0 };
+
+        Some(increase * (sampled + extrapolate(to_start) + extrapolate(to_end)) / sampled)
+    }
+
+    /// rate returns the average increase of the counter per unit of time over the window,
+    /// extrapolated as `increase` is
+    pub fn rate(&self) -> Option<f64> {
+        self.increase().map(|increase| increase / (self.end - self.start) as f64)
+    }
+
+    /// irate returns the increase of the counter per unit of time between the last two
+    /// `DataPoint`s in the window, or None if there are fewer than two
+    pub fn irate(&self) -> Option<f64> {
+        let (previous, last) = match (self.previous, self.last) {
+            (Some(previous), Some(last)) if last.time > previous.time => (previous, last),
+            _ => return None,
+        };
+
+        let increase = if last.value < previous.value {
+            last.value as f64
+        } else {
+            (last.value - previous.value) as f64
+        };
+        Some(increase / (last.time - previous.time) as f64)
+    }
+}
+
+// counter consumes every decoder in decoders, in order, into a Counter over the window
+fn counter<D: Decode>(decoders: &mut [D], start: u64, end: u64) -> Result<Counter, decode::Error> {
+    let mut counter = Counter::new(start, end);
+    for decoder in decoders {
+        counter.consume(decoder)?;
+    }
+
+    Ok(counter)
+}
+
+/// increase returns how much the counter read from `decoders`, which hold consecutive blocks of
+/// one series oldest first, increased between `start`, inclusive, and `end`, exclusive. See
+/// `Counter::increase`
+pub fn increase<D: Decode>(decoders: &mut [D],
+                           start: u64,
+                           end: u64)
+                           -> Result<Option<f64>, decode::Error> {
+    Ok(counter(decoders, start, end)?.increase())
+}
+
+/// rate returns the average increase per unit of time of the counter read from `decoders` between
+/// `start`, inclusive, and `end`, exclusive. See `Counter::rate`
+pub fn rate<D: Decode>(decoders: &mut [D],
+                       start: u64,
+                       end: u64)
+                       -> Result<Option<f64>, decode::Error> {
+    Ok(counter(decoders, start, end)?.rate())
+}
+
+/// irate returns the increase per unit of time of the counter read from `decoders` between its
+/// last two `DataPoint`s before `end`, exclusive, and at or after `start`. See `Counter::irate`
+pub fn irate<D: Decode>(decoders: &mut [D],
+                        start: u64,
+                        end: u64)
+                        -> Result<Option<f64>, decode::Error> {
+    Ok(counter(decoders, start, end)?.irate())
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use test_util::decoder;
+    use super::{Counter, increase, irate, rate};
+
+    #[test]
+    fn counter_reset() {
+        // the counter resets between 1050 and 1060
+        let values = [0, 10, 20, 30, 40, 5, 15, 25, 35];
+        let datapoints: Vec<DataPoint> = values.iter()
+            .enumerate()
+            .map(|(i, &value)| DataPoint::new(1000 + 10 + i as u64 * 10, value))
+            .collect();
+
+        // split the series across two blocks
+        let mut decoders = [decoder(&datapoints[..4]), decoder(&datapoints[4..])];
+        let mut counter = Counter::new(1000, 1100);
+        for decoder in &mut decoders {
+            counter.consume(decoder).unwrap();
+        }
+
+        // 75 over the 80 seconds sampled, extrapolated to the end of the window but not back past
+        // zero at the start
+        assert_eq!(counter.increase(), Some(75.0 * 90.0 / 80.0));
+        assert_eq!(counter.rate(), Some(75.0 * 90.0 / 80.0 / 100.0));
+        assert_eq!(counter.irate(), Some(1.0));
+    }
+
+    #[test]
+    fn counter_extrapolation() {
+        let datapoints: Vec<DataPoint> = (0..9)
+            .map(|i| DataPoint::new(1000 + 10 + i * 10, 100 + i as i64 * 10))
+            .collect();
+
+        // the series covers the window so it is extrapolated to both edges
+        assert_eq!(increase(&mut [decoder(&datapoints)], 1000, 1100).unwrap(), Some(100.0));
+        assert_eq!(rate(&mut [decoder(&datapoints)], 1000, 1100).unwrap(), Some(1.0));
+
+        // the series stops long before the end of the window so it is only extrapolated by half
+        // the average interval
+        assert_eq!(increase(&mut [decoder(&datapoints)], 1000, 1200).unwrap(),
+                   Some(80.0 * 95.0 / 80.0));
+
+        // a window holding the last two DataPoints
+        assert_eq!(irate(&mut [decoder(&datapoints)], 1075, 1100).unwrap(), Some(1.0));
+        assert_eq!(irate(&mut [decoder(&datapoints)], 1085, 1100).unwrap(), None);
+        assert_eq!(increase(&mut [decoder(&datapoints)], 1085, 1100).unwrap(), None);
+    }
+}
@@ -0,0 +1,179 @@
+use Decode;
+use decode;
+use decode::interpolate::{Interpolator, interpolate};
+
+/// Fill
+///
+/// Fill determines the value of a point on the grid of a `Resampler` when no `DataPoint` falls
+/// within its step.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Fill {
+    /// Repeat the value of the last `DataPoint` before the point.
+    Previous,
+
+    /// Interpolate linearly between the `DataPoint`s on either side of the point, rounding to
+    /// the nearest integer.
+    Linear,
+
+    /// Leave the point without a value, as NaN would for a floating point value.
+    Null,
+
+    /// Use zero.
+    Zero,
+}
+
+/// Resampler
+///
+/// Resampler is an iterator which resamples the `DataPoint`s of a decoder onto a grid of points
+/// `step` apart. Each point takes the value of the last `DataPoint` at or before it, and after the
+/// point before it, so a point never takes a value from the future. Points whose step holds no
+/// `DataPoint` are filled according to `Fill`, as long as the `DataPoint`s on either side of the
+/// point are no more than `max_gap` apart. Points before the first `DataPoint` or after the last
+/// are never filled.
+#[derive(Debug)]
+pub struct Resampler<D: Decode> {
+    interpolator: Interpolator<D>, // finds the DataPoints on either side of each point
+    time: u64, // next point on the grid
+    end: u64, // end of the grid, exclusive
+    step: u64,
+    fill: Fill,
+    max_gap: u64,
+}
+
+impl<D> Resampler<D>
+    where D: Decode
+{
+    /// new creates a new Resampler of the `DataPoint`s decoded by `decoder` onto a grid of points
+    /// `step` apart from `start`, inclusive, to `end`, exclusive. By default points are filled
+    /// with Fill::Null and there is no max gap
+    pub fn new(decoder: D, start: u64, end: u64, step: u64) -> Self {
+        assert!(step > 0, "step must be greater than zero");
+
+        Resampler {
+            interpolator: Interpolator::new(decoder),
+            time: start,
+            end,
+            step,
+            fill: Fill::Null,
+            max_gap: u64::MAX,
+        }
+    }
+
+    /// fill sets how points whose step holds no `DataPoint` are filled
+    pub fn fill(mut self, fill: Fill) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// max_gap sets the largest gap between `DataPoint`s which is filled, points in larger gaps
+    /// are left without a value
+    pub fn max_gap(mut self, max_gap: u64) -> Self {
+        self.max_gap = max_gap;
+        self
+    }
+
+    // value returns the value of the point on the grid at time
+    fn value(&mut self, time: u64) -> Result<Option<i64>, decode::Error> {
+        let (previous, next) = match self.interpolator.neighbours(time)? {
+            (Some(previous), _) if previous.time > time.saturating_sub(self.step) => {
+                return Ok(Some(previous.value));
+            }
+            (Some(previous), Some(next)) if next.time - previous.time <= self.max_gap => {
+                (previous, next)
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(match self.fill {
+            Fill::Previous => Some(previous.value),
+            Fill::Linear => Some(interpolate(previous, next, time).round() as i64),
+            Fill::Null => None,
+            Fill::Zero => Some(0),
+        })
+    }
+}
+
+impl<D> Iterator for Resampler<D>
+    where D: Decode
+{
+    type Item = Result<(u64, Option<i64>), decode::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.time >= self.end {
+            return None;
+        }
+
+        let time = self.time;
+        self.time = self.time.saturating_add(self.step);
+
+        match self.value(time) {
+            Ok(value) => Some(Ok((time, value))),
+            Err(err) => {
+                self.time = self.end;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {DataPoint, StdDecoder, SimplePredictor};
+    use stream::BufferedReader;
+    use test_util;
+    use super::{Fill, Resampler};
+
+    fn decoder() -> StdDecoder<BufferedReader, SimplePredictor> {
+        // a gap of 40 seconds after 1025 and of 100 seconds after 1070
+        let datapoints: Vec<DataPoint> =
+            [(1005, 10), (1012, 20), (1025, 30), (1065, 70), (1070, 80), (1170, 0)]
+                .iter()
+                .map(|&(time, value)| DataPoint::new(time, value))
+                .collect();
+        test_util::decoder(&datapoints)
+    }
+
+    fn resample(fill: Fill) -> Vec<Option<i64>> {
+        Resampler::new(decoder(), 1000, 1200, 10)
+            .fill(fill)
+            .max_gap(50)
+            .map(|point| point.unwrap().1)
+            .collect()
+    }
+
+    #[test]
+    fn resample_fills() {
+        let times: Vec<u64> = Resampler::new(decoder(), 1000, 1200, 10)
+            .map(|point| point.unwrap().0)
+            .collect();
+        assert_eq!(times, (0..20).map(|i| 1000 + i * 10).collect::<Vec<u64>>());
+
+        let n = None;
+        // 1070 takes the value at 1070 rather than the one at 1065
+        assert_eq!(resample(Fill::Null),
+                   vec![n, Some(10), Some(20), Some(30), n, n, n, Some(80), n, n, n, n, n, n, n,
+                        n, n, Some(0), n, n]);
+        assert_eq!(resample(Fill::Previous),
+                   vec![n, Some(10), Some(20), Some(30), Some(30), Some(30), Some(30), Some(80), n,
+                        n, n, n, n, n, n, n, n, Some(0), n, n]);
+        assert_eq!(resample(Fill::Zero),
+                   vec![n, Some(10), Some(20), Some(30), Some(0), Some(0), Some(0), Some(80), n,
+                        n, n, n, n, n, n, n, n, Some(0), n, n]);
+
+        // 1040 is 15 seconds into the 40 seconds between 30 and 70
+        assert_eq!(resample(Fill::Linear),
+                   vec![n, Some(10), Some(20), Some(30), Some(45), Some(55), Some(65), Some(80), n,
+                        n, n, n, n, n, n, n, n, Some(0), n, n]);
+    }
+
+    #[test]
+    fn resample_without_max_gap() {
+        let values: Vec<Option<i64>> = Resampler::new(decoder(), 1060, 1180, 20)
+            .fill(Fill::Linear)
+            .map(|point| point.unwrap().1)
+            .collect();
+
+        // the 100 seconds between 1070 and 1170 are filled when there is no max gap
+        assert_eq!(values, vec![Some(65), Some(80), Some(56), Some(40), Some(24), Some(8)]);
+    }
+}
@@ -0,0 +1,425 @@
+use std::{error, fmt, io};
+use std::convert::TryFrom;
+
+use checksum;
+use decode::std_decoder::StdDecoder;
+use predictor::SimplePredictor;
+use store::Block;
+use stream::SliceReader;
+
+// A block file holds the compressed chunks of many series followed by an index of them:
+//
+//   MAGIC | chunk | chunk | ... | index entry | index entry | ... | footer
+//
+// Every index entry is INDEX_ENTRY_LEN bytes long and holds, in order, the series ID (u64), the
+// minimum and maximum timestamps (u64), the number of DataPoints (u32), the offset (u64) and
+// length (u32) of the chunk, and a CRC32C of the chunk (u32). Entries are sorted by series ID and
+// then by minimum timestamp. The footer holds the offset of the first index entry (u64), the
+// number of index entries (u32), a CRC32C of the index entries (u32) and finally MAGIC again, so
+// a reader can start from the end of the file. All integers are big endian.
+
+/// MAGIC starts and ends every block file
+pub const MAGIC: [u8; 4] = *b"TSZB";
+
+/// INDEX_ENTRY_LEN is the length, in bytes, of an index entry
+pub const INDEX_ENTRY_LEN: usize = 44;
+
+/// FOOTER_LEN is the length, in bytes, of the footer
+pub const FOOTER_LEN: usize = 20;
+
+/// Error
+///
+/// Error encapsulates the potential errors that can be encountered when reading a block file
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    InvalidMagic,
+    Truncated,
+    IndexChecksumMismatch,
+    ChunkChecksumMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidMagic => write!(f, "Block file does not start and end with MAGIC"),
+            Error::Truncated => write!(f, "Block file is shorter than its index requires"),
+            Error::IndexChecksumMismatch => write!(f, "Block file index checksum did not match"),
+            Error::ChunkChecksumMismatch => write!(f, "Block file chunk checksum did not match"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::InvalidMagic => "Block file does not start and end with MAGIC",
+            Error::Truncated => "Block file is shorter than its index requires",
+            Error::IndexChecksumMismatch => "Block file index checksum did not match",
+            Error::ChunkChecksumMismatch => "Block file chunk checksum did not match",
+        }
+    }
+}
+
+/// IndexEntry
+///
+/// IndexEntry describes a single chunk in a block file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct IndexEntry {
+    series: u64,
+    min_time: u64,
+    max_time: u64,
+    count: u32,
+    offset: u64,
+    len: u32,
+    checksum: u32,
+}
+
+impl IndexEntry {
+    /// series returns the ID of the series the chunk belongs to
+    pub fn series(&self) -> u64 {
+        self.series
+    }
+
+    /// min_time returns the timestamp of the first `DataPoint` in the chunk
+    pub fn min_time(&self) -> u64 {
+        self.min_time
+    }
+
+    /// max_time returns the timestamp of the last `DataPoint` in the chunk
+    pub fn max_time(&self) -> u64 {
+        self.max_time
+    }
+
+    /// count returns the number of `DataPoint`s in the chunk
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// offset returns the offset of the chunk from the start of the file
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// len returns the length of the chunk in bytes
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// is_empty returns true if the chunk has no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// checksum returns the CRC32C of the chunk
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    fn to_bytes(self) -> [u8; INDEX_ENTRY_LEN] {
+        let mut bytes = [0; INDEX_ENTRY_LEN];
+        bytes[0..8].copy_from_slice(&self.series.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.min_time.to_be_bytes());
+        bytes[16..24].copy_from_slice(&self.max_time.to_be_bytes());
+        bytes[24..28].copy_from_slice(&self.count.to_be_bytes());
+        bytes[28..36].copy_from_slice(&self.offset.to_be_bytes());
+        bytes[36..40].copy_from_slice(&self.len.to_be_bytes());
+        bytes[40..44].copy_from_slice(&self.checksum.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        IndexEntry {
+            series: read_u64(&bytes[0..8]),
+            min_time: read_u64(&bytes[8..16]),
+            max_time: read_u64(&bytes[16..24]),
+            count: read_u32(&bytes[24..28]),
+            offset: read_u64(&bytes[28..36]),
+            len: read_u32(&bytes[36..40]),
+            checksum: read_u32(&bytes[40..44]),
+        }
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_be_bytes(buf)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_be_bytes(buf)
+}
+
+/// BlockFileWriter
+///
+/// BlockFileWriter writes chunks to a block file, the index is written when it is finished.
+#[derive(Debug)]
+pub struct BlockFileWriter<W: io::Write> {
+    w: W,
+    offset: u64, // number of bytes written so far
+    entries: Vec<IndexEntry>,
+}
+
+impl<W> BlockFileWriter<W>
+    where W: io::Write
+{
+    /// new creates a new BlockFileWriter which writes a block file to `w`
+    pub fn new(mut w: W) -> io::Result<Self> {
+        w.write_all(&MAGIC)?;
+
+        Ok(BlockFileWriter {
+            w,
+            offset: MAGIC.len() as u64,
+            entries: Vec::new(),
+        })
+    }
+
+    /// write_chunk writes `bytes`, which hold `count` `DataPoint`s of the series `series` with
+    /// timestamps from `min_time` to `max_time` encoded by `StdEncoder`. An error of kind
+    /// `InvalidInput` is returned, and nothing is written, if `bytes` is longer than 2^32 - 1 bytes
+    pub fn write_chunk(&mut self,
+                       series: u64,
+                       min_time: u64,
+                       max_time: u64,
+                       count: u32,
+                       bytes: &[u8])
+                       -> io::Result<()> {
+        let len = u32::try_from(bytes.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           "chunks can be at most 2^32 - 1 bytes long")
+        })?;
+
+        self.w.write_all(bytes)?;
+        self.entries.push(IndexEntry {
+            series,
+            min_time,
+            max_time,
+            count,
+            offset: self.offset,
+            len,
+            checksum: checksum::crc32c(bytes),
+        });
+        self.offset += bytes.len() as u64;
+
+        Ok(())
+    }
+
+    /// write_block writes the chunk of a sealed `Block` of the series `series`. An error of kind
+    /// `InvalidInput` is returned, and nothing is written, if the block holds more than 2^32 - 1
+    /// `DataPoint`s
+    pub fn write_block(&mut self, series: u64, block: &Block) -> io::Result<()> {
+        let count = u32::try_from(block.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           "chunks can hold at most 2^32 - 1 DataPoints")
+        })?;
+
+        self.write_chunk(series, block.min_time(), block.max_time(), count, block.bytes())
+    }
+
+    /// finish writes the index and footer and returns the underlying writer. An error of kind
+    /// `InvalidInput` is returned, and nothing is written, if more than 2^32 - 1 chunks were
+    /// written
+    pub fn finish(mut self) -> io::Result<W> {
+        let count = u32::try_from(self.entries.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           "block files can hold at most 2^32 - 1 chunks")
+        })?;
+
+        self.entries.sort_by_key(|entry| (entry.series, entry.min_time));
+
+        let mut index = Vec::with_capacity(self.entries.len() * INDEX_ENTRY_LEN);
+        for entry in &self.entries {
+            index.extend_from_slice(&entry.to_bytes());
+        }
+        self.w.write_all(&index)?;
+
+        let mut footer = [0; FOOTER_LEN];
+        footer[0..8].copy_from_slice(&self.offset.to_be_bytes());
+        footer[8..12].copy_from_slice(&count.to_be_bytes());
+        footer[12..16].copy_from_slice(&checksum::crc32c(&index).to_be_bytes());
+        footer[16..20].copy_from_slice(&MAGIC);
+        self.w.write_all(&footer)?;
+
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+/// BlockFileReader
+///
+/// BlockFileReader reads a block file from a slice of bytes, such as a memory mapped file. Only
+/// the index is read up front, chunks are checked and decoded from the slice when asked for.
+#[derive(Debug)]
+pub struct BlockFileReader<'a> {
+    bytes: &'a [u8],
+    entries: Vec<IndexEntry>,
+}
+
+impl<'a> BlockFileReader<'a> {
+    /// new creates a new BlockFileReader over `bytes`, reading and checking the index
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        if bytes.len() < MAGIC.len() + FOOTER_LEN {
+            return Err(Error::Truncated);
+        }
+
+        let footer = &bytes[bytes.len() - FOOTER_LEN..];
+        if bytes[..MAGIC.len()] != MAGIC || footer[16..20] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let index_offset = read_u64(&footer[0..8]);
+        let num_entries = read_u32(&footer[8..12]) as u64;
+        let index_end = (bytes.len() - FOOTER_LEN) as u64;
+        let index_len = num_entries * INDEX_ENTRY_LEN as u64;
+        if index_offset < MAGIC.len() as u64 ||
+           index_offset.checked_add(index_len) != Some(index_end) {
+            return Err(Error::Truncated);
+        }
+
+        let index = &bytes[index_offset as usize..index_end as usize];
+        if checksum::crc32c(index) != read_u32(&footer[12..16]) {
+            return Err(Error::IndexChecksumMismatch);
+        }
+
+        let entries: Vec<IndexEntry> = index.chunks(INDEX_ENTRY_LEN)
+            .map(IndexEntry::from_bytes)
+            .collect();
+        let in_bounds = |entry: &IndexEntry| {
+            entry.offset.checked_add(entry.len as u64).is_some_and(|end| end <= index_offset)
+        };
+        if !entries.iter().all(in_bounds) {
+            return Err(Error::Truncated);
+        }
+
+        Ok(BlockFileReader { bytes, entries })
+    }
+
+    /// entries returns the index entries of every chunk in the file, sorted by series ID and then
+    /// by minimum timestamp
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// series returns the index entries of the chunks of the series `series`, sorted by minimum
+    /// timestamp
+    pub fn series(&self, series: u64) -> &[IndexEntry] {
+        let start = self.entries.partition_point(|entry| entry.series < series);
+        let end = self.entries.partition_point(|entry| entry.series <= series);
+        &self.entries[start..end]
+    }
+
+    /// chunk returns the bytes of the chunk described by `entry` after checking its checksum.
+    /// `Error::Truncated` is returned if `entry`, which may come from another block file, lies
+    /// outside of the file
+    pub fn chunk(&self, entry: &IndexEntry) -> Result<&'a [u8], Error> {
+        let start = usize::try_from(entry.offset).map_err(|_| Error::Truncated)?;
+        let chunk = start.checked_add(entry.len as usize)
+            .and_then(|end| self.bytes.get(start..end))
+            .ok_or(Error::Truncated)?;
+        if checksum::crc32c(chunk) != entry.checksum {
+            return Err(Error::ChunkChecksumMismatch);
+        }
+
+        Ok(chunk)
+    }
+
+    /// decoder returns a `StdDecoder` which decodes the chunk described by `entry` directly from
+    /// the underlying slice
+    pub fn decoder(&self,
+                   entry: &IndexEntry)
+                   -> Result<StdDecoder<SliceReader<'a>, SimplePredictor>, Error> {
+        let chunk = self.chunk(entry)?;
+        Ok(StdDecoder::new(SliceReader::new(chunk), SimplePredictor::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use decode;
+    use store::Store;
+    use super::{BlockFileWriter, BlockFileReader, Error, FOOTER_LEN};
+
+    fn write_store() -> (Store<u64>, Vec<u8>) {
+        let mut store = Store::with_block_duration(1000);
+        for i in 0..300 {
+            for series in &[3, 1, 2] {
+                store.append(*series, DataPoint::new(i * 10 + series, (i * series) as i64))
+                    .unwrap();
+            }
+        }
+        store.seal(u64::MAX);
+
+        let mut w = BlockFileWriter::new(Vec::new()).unwrap();
+        for series in &[3, 1, 2] {
+            for block in store.sealed_blocks(series) {
+                w.write_block(*series, block).unwrap();
+            }
+        }
+
+        (store, w.finish().unwrap())
+    }
+
+    #[test]
+    fn write_and_read() {
+        let (store, bytes) = write_store();
+        let r = BlockFileReader::new(&bytes).unwrap();
+
+        assert_eq!(r.entries().len(), 9);
+        assert_eq!(r.series(4).len(), 0);
+
+        for series in &[1, 2, 3] {
+            let entries = r.series(*series);
+            let blocks = store.sealed_blocks(series);
+            assert_eq!(entries.len(), blocks.len());
+
+            for (entry, block) in entries.iter().zip(blocks) {
+                assert_eq!(entry.series(), *series);
+                assert_eq!(entry.min_time(), block.min_time());
+                assert_eq!(entry.max_time(), block.max_time());
+                assert_eq!(entry.count() as usize, block.len());
+                assert_eq!(r.chunk(entry).unwrap(), block.bytes());
+                assert_eq!(decode::decode_all(&mut r.decoder(entry).unwrap()).unwrap(),
+                           block.datapoints().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn read_damaged_file() {
+        let (_, bytes) = write_store();
+
+        let entry = BlockFileReader::new(&bytes).unwrap().entries()[0];
+        let mut damaged = bytes.clone();
+        damaged[entry.offset() as usize + 10] ^= 1;
+        let r = BlockFileReader::new(&damaged).unwrap();
+        assert_eq!(r.chunk(&entry).err().unwrap(), Error::ChunkChecksumMismatch);
+        assert!(r.chunk(&r.entries()[1]).is_ok());
+
+        let mut damaged = bytes.clone();
+        let index = damaged.len() - FOOTER_LEN - 1;
+        damaged[index] ^= 1;
+        assert_eq!(BlockFileReader::new(&damaged).err().unwrap(),
+                   Error::IndexChecksumMismatch);
+
+        let mut damaged = bytes.clone();
+        damaged[0] = 0;
+        assert_eq!(BlockFileReader::new(&damaged).err().unwrap(), Error::InvalidMagic);
+
+        assert_eq!(BlockFileReader::new(&bytes[..10]).err().unwrap(), Error::Truncated);
+
+        // an entry from a larger block file lies outside of a smaller one
+        let mut store = Store::with_block_duration(1000);
+        store.append(1, DataPoint::new(10, 1)).unwrap();
+        store.seal(u64::MAX);
+        let mut w = BlockFileWriter::new(Vec::new()).unwrap();
+        w.write_block(1, &store.sealed_blocks(&1)[0]).unwrap();
+        let small = w.finish().unwrap();
+        let r = BlockFileReader::new(&small).unwrap();
+        let last = *BlockFileReader::new(&bytes).unwrap().entries().last().unwrap();
+        assert_eq!(r.chunk(&last).err().unwrap(), Error::Truncated);
+        assert_eq!(r.decoder(&last).err().unwrap(), Error::Truncated);
+    }
+}
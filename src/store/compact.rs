@@ -0,0 +1,134 @@
+use {DataPoint, StdDecoder, SimplePredictor};
+use decode;
+use store::{Block, Error, Store};
+use stream::SliceReader;
+
+/// DuplicatePolicy
+///
+/// DuplicatePolicy determines what happens when more than one `DataPoint` with the same timestamp
+/// is found while merging streams.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DuplicatePolicy {
+    /// Keep the `DataPoint` from the stream which comes first.
+    First,
+
+    /// Keep the `DataPoint` from the stream which comes last.
+    Last,
+
+    /// Fail with `Error::DuplicateTimestamp`.
+    Error,
+}
+
+/// merge decodes the streams written by `StdEncoder` in `streams` and merges their `DataPoint`s
+/// in order of time, resolving duplicate timestamps using `policy`. The order of `streams`
+/// decides which `DataPoint` comes first when timestamps are equal
+pub fn merge(streams: &[&[u8]], policy: DuplicatePolicy) -> Result<Vec<DataPoint>, Error> {
+    let mut datapoints = Vec::new();
+    for stream in streams {
+        let mut decoder = StdDecoder::new(SliceReader::new(stream), SimplePredictor::new());
+        datapoints.extend(decode::decode_all(&mut decoder)?);
+    }
+
+    // the sort is stable so DataPoints with equal timestamps stay in the order of their streams
+    datapoints.sort_by_key(|dp| dp.time);
+
+    let mut merged: Vec<DataPoint> = Vec::with_capacity(datapoints.len());
+    for dp in datapoints {
+        match merged.last_mut() {
+            Some(last) if last.time == dp.time => {
+                match policy {
+                    DuplicatePolicy::First => {}
+                    DuplicatePolicy::Last => *last = dp,
+                    DuplicatePolicy::Error => return Err(Error::DuplicateTimestamp(dp.time)),
+                }
+            }
+            _ => merged.push(dp),
+        }
+    }
+
+    Ok(merged)
+}
+
+/// compact merges the streams in `streams`, which must all belong to the same series, as `merge`
+/// does and re-encodes the result into blocks which are `block_duration` long
+pub fn compact(streams: &[&[u8]],
+               policy: DuplicatePolicy,
+               block_duration: u64)
+               -> Result<Vec<Block>, Error> {
+    let mut store = Store::with_block_duration(block_duration);
+    for dp in merge(streams, policy)? {
+        store.append((), dp)?;
+    }
+    store.seal(u64::MAX);
+
+    Ok(store.series.remove(&()).map_or(Vec::new(), |series| series.sealed))
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use store::{Block, Error, Store};
+    use super::{DuplicatePolicy, compact, merge};
+
+    // encode writes datapoints to blocks which are block_duration long
+    fn encode(datapoints: &[DataPoint], block_duration: u64) -> Vec<Block> {
+        let mut store = Store::with_block_duration(block_duration);
+        for dp in datapoints {
+            store.append(0, *dp).unwrap();
+        }
+        store.seal(u64::MAX);
+        store.sealed_blocks(&0).to_vec()
+    }
+
+    #[test]
+    fn merge_duplicates() {
+        let a = encode(&[DataPoint::new(10, 1), DataPoint::new(20, 2), DataPoint::new(30, 3)],
+                       100);
+        let b = encode(&[DataPoint::new(15, 4), DataPoint::new(20, 5), DataPoint::new(40, 6)],
+                       100);
+        let streams = [a[0].bytes(), b[0].bytes()];
+
+        assert_eq!(merge(&streams, DuplicatePolicy::First).unwrap(),
+                   vec![DataPoint::new(10, 1),
+                        DataPoint::new(15, 4),
+                        DataPoint::new(20, 2),
+                        DataPoint::new(30, 3),
+                        DataPoint::new(40, 6)]);
+        assert_eq!(merge(&streams, DuplicatePolicy::Last).unwrap(),
+                   vec![DataPoint::new(10, 1),
+                        DataPoint::new(15, 4),
+                        DataPoint::new(20, 5),
+                        DataPoint::new(30, 3),
+                        DataPoint::new(40, 6)]);
+        assert_eq!(merge(&streams, DuplicatePolicy::Error).err().unwrap(),
+                   Error::DuplicateTimestamp(20));
+    }
+
+    #[test]
+    fn compact_small_blocks() {
+        let datapoints: Vec<DataPoint> = (0..600)
+            .map(|i| DataPoint::new(i * 6, i as i64))
+            .collect();
+
+        // split the DataPoints into many small blocks, alternating between two streams as if
+        // they had been written by two processes
+        let (even, odd): (Vec<DataPoint>, Vec<DataPoint>) =
+            datapoints.iter().partition(|dp| dp.value % 2 == 0);
+        let mut blocks = encode(&even, 60);
+        blocks.extend(encode(&odd, 60));
+        assert_eq!(blocks.len(), 120);
+
+        let streams: Vec<&[u8]> = blocks.iter().map(|block| block.bytes()).collect();
+        let compacted = compact(&streams, DuplicatePolicy::Error, 1200).unwrap();
+
+        assert_eq!(compacted.len(), 3);
+        assert_eq!(compacted[1].start(), 1200);
+        assert_eq!(compacted[1].len(), 200);
+
+        let mut decoded = Vec::new();
+        for block in &compacted {
+            decoded.extend(block.datapoints().unwrap());
+        }
+        assert_eq!(decoded, datapoints);
+    }
+}
@@ -0,0 +1,437 @@
+use std::{error, fmt};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use {DataPoint, Encode};
+use decode;
+use encode::std_encoder::StdEncoder;
+use decode::std_decoder::StdDecoder;
+use predictor::SimplePredictor;
+use stream::{BufferedWriter, SliceReader};
+
+/// DEFAULT_BLOCK_DURATION is the default length of a block in seconds, two hours as in the
+/// Gorilla paper
+pub const DEFAULT_BLOCK_DURATION: u64 = 2 * 60 * 60;
+
+/// Error
+///
+/// Error encapsulates the potential errors that can be encountered when using a `Store`
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Decode(decode::Error),
+    OutOfOrder,
+    /// Sealed is returned when a `DataPoint` falls within the window of a block which has already
+    /// been sealed, the `DataPoint` arrived too late to be appended
+    Sealed,
+    DuplicateTimestamp(u64),
+    TooLate,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Decode(ref err) => write!(f, "Decode error: {}", err),
+            Error::OutOfOrder => write!(f, "DataPoint is older than the last DataPoint appended"),
+            Error::Sealed => write!(f, "DataPoint falls within a block which has been sealed"),
+            Error::DuplicateTimestamp(time) => {
+                write!(f, "Found more than one DataPoint with timestamp {}", time)
+            }
+            Error::TooLate => write!(f, "DataPoint arrived after newer DataPoints were committed"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    #[allow(deprecated)]
+    fn description(&self) -> &str {
+        match *self {
+            Error::Decode(ref err) => err.description(),
+            Error::OutOfOrder => "DataPoint is older than the last DataPoint appended",
+            Error::Sealed => "DataPoint falls within a block which has been sealed",
+            Error::DuplicateTimestamp(_) => "Found more than one DataPoint with the same timestamp",
+            Error::TooLate => "DataPoint arrived after newer DataPoints were committed",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Decode(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<decode::Error> for Error {
+    fn from(err: decode::Error) -> Error {
+        Error::Decode(err)
+    }
+}
+
+/// Block
+///
+/// Block is a sealed block of `DataPoint`s which all fall within the same window of time,
+/// compressed with `StdEncoder`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    start: u64, // start of the window, inclusive
+    end: u64, // end of the window, exclusive
+    min_time: u64, // timestamp of the first DataPoint
+    max_time: u64, // timestamp of the last DataPoint
+    count: usize, // number of DataPoints in the block
+    bytes: Box<[u8]>,
+}
+
+impl Block {
+    /// new creates a new Block covering the window from `start` to `end` from `count`
+    /// `DataPoint`s, whose timestamps run from `min_time` to `max_time`, encoded in `bytes`
+    pub fn new(start: u64,
+               end: u64,
+               min_time: u64,
+               max_time: u64,
+               count: usize,
+               bytes: Box<[u8]>)
+               -> Self {
+        Block {
+            start,
+            end,
+            min_time,
+            max_time,
+            count,
+            bytes,
+        }
+    }
+
+    /// start returns the start of the window covered by the block, inclusive
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// end returns the end of the window covered by the block, exclusive
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// min_time returns the timestamp of the first `DataPoint` in the block
+    pub fn min_time(&self) -> u64 {
+        self.min_time
+    }
+
+    /// max_time returns the timestamp of the last `DataPoint` in the block
+    pub fn max_time(&self) -> u64 {
+        self.max_time
+    }
+
+    /// len returns the number of `DataPoint`s in the block
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// is_empty returns true if the block contains no `DataPoint`s
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// bytes returns the encoded `DataPoint`s
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// decoder returns a decoder of the `DataPoint`s in the block, which reads the block's bytes
+    /// in place
+    pub fn decoder<'a>(&'a self) -> StdDecoder<SliceReader<'a>, SimplePredictor> {
+        StdDecoder::new(SliceReader::new(&self.bytes), SimplePredictor::new())
+    }
+
+    /// datapoints decodes all of the `DataPoint`s in the block
+    pub fn datapoints(&self) -> Result<Vec<DataPoint>, decode::Error> {
+        decode::decode_all(&mut self.decoder())
+    }
+}
+
+// HeadBlock is the block currently being appended to
+#[derive(Debug, Clone)]
+struct HeadBlock {
+    start: u64,
+    end: u64,
+    count: usize,
+    first_time: u64,
+    last_time: u64,
+    encoder: StdEncoder<BufferedWriter, SimplePredictor>,
+}
+
+impl HeadBlock {
+    fn new(start: u64, end: u64, dp: DataPoint) -> Self {
+        // starting the encoder at the first DataPoint means the first delta is always zero, so
+        // it fits in the 14 bits StdEncoder allows for it whatever the unit of time
+        let mut encoder = StdEncoder::new(dp.time, BufferedWriter::new(), SimplePredictor::new());
+        encoder.encode(dp);
+
+        HeadBlock {
+            start,
+            end,
+            count: 1,
+            first_time: dp.time,
+            last_time: dp.time,
+            encoder,
+        }
+    }
+
+    fn append(&mut self, dp: DataPoint) {
+        self.encoder.encode(dp);
+        self.count += 1;
+        self.last_time = dp.time;
+    }
+
+    fn seal(self) -> Block {
+        Block::new(self.start,
+                   self.end,
+                   self.first_time,
+                   self.last_time,
+                   self.count,
+                   self.encoder.close())
+    }
+
+    // snapshot returns a sealed copy of the block, leaving the block open for appends
+    fn snapshot(&self) -> Block {
+        self.clone().seal()
+    }
+}
+
+// Series holds the blocks of a single series, ordered by time
+#[derive(Debug, Clone, Default)]
+struct Series {
+    head: Option<HeadBlock>,
+    sealed: Vec<Block>,
+}
+
+/// Store
+///
+/// Store is an in-memory store of series, each identified by a key of type `K`. The `DataPoint`s
+/// of a series are split into blocks which each cover a fixed window of time. `DataPoint`s are
+/// appended to the series' head block until one arrives for a later window, at which point the
+/// head block is sealed and a new head block is started.
+#[derive(Debug)]
+pub struct Store<K: Hash + Eq> {
+    block_duration: u64,
+    series: HashMap<K, Series>,
+}
+
+impl<K> Store<K>
+    where K: Hash + Eq
+{
+    /// new creates a new Store whose blocks are DEFAULT_BLOCK_DURATION long
+    pub fn new() -> Self {
+        Store::with_block_duration(DEFAULT_BLOCK_DURATION)
+    }
+
+    /// with_block_duration creates a new Store whose blocks are `block_duration` long, it must be
+    /// in the same unit as the timestamps of the `DataPoint`s appended
+    pub fn with_block_duration(block_duration: u64) -> Self {
+        assert!(block_duration > 0, "block duration must be greater than zero");
+
+        Store {
+            block_duration,
+            series: HashMap::new(),
+        }
+    }
+
+    /// block_duration returns the length of the window covered by each block
+    pub fn block_duration(&self) -> u64 {
+        self.block_duration
+    }
+
+    /// len returns the number of series in the store
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    /// is_empty returns true if the store contains no series
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+
+    /// append adds `dp` to the series identified by `key`, creating the series if it does not
+    /// exist. `DataPoint`s must be appended in order of time, an `Error::OutOfOrder` is returned
+    /// if `dp` is older than the last `DataPoint` appended to the series and an `Error::Sealed` if
+    /// it falls within the window of a block which has already been sealed
+    pub fn append(&mut self, key: K, dp: DataPoint) -> Result<(), Error> {
+        let block_start = dp.time - dp.time % self.block_duration;
+        let block_end = block_start.saturating_add(self.block_duration);
+        let series = self.series.entry(key).or_default();
+
+        if let Some(ref mut head) = series.head {
+            if dp.time < head.last_time {
+                return Err(Error::OutOfOrder);
+            }
+            if head.start == block_start {
+                head.append(dp);
+                return Ok(());
+            }
+        } else if let Some(block) = series.sealed.last() {
+            if dp.time < block.max_time {
+                return Err(Error::OutOfOrder);
+            }
+            if dp.time < block.end {
+                return Err(Error::Sealed);
+            }
+        }
+
+        if let Some(head) = series.head.take() {
+            series.sealed.push(head.seal());
+        }
+        series.head = Some(HeadBlock::new(block_start, block_end, dp));
+        Ok(())
+    }
+
+    /// seal seals every head block whose window ends at or before `time`, so that all of the
+    /// `DataPoint`s older than `time` are in sealed blocks
+    pub fn seal(&mut self, time: u64) {
+        for series in self.series.values_mut() {
+            if series.head.as_ref().is_some_and(|head| head.end <= time) {
+                let head = series.head.take().unwrap();
+                series.sealed.push(head.seal());
+            }
+        }
+    }
+
+    /// sealed_blocks returns the sealed blocks of the series identified by `key`, ordered by time
+    pub fn sealed_blocks(&self, key: &K) -> &[Block] {
+        self.series.get(key).map_or(&[], |series| &series.sealed[..])
+    }
+
+    /// blocks returns copies of the blocks of the series identified by `key` which overlap the
+    /// window between `start`, inclusive, and `end`, exclusive. The head block is included as a
+    /// sealed snapshot of the `DataPoint`s appended to it so far
+    pub fn blocks(&self, key: &K, start: u64, end: u64) -> Vec<Block> {
+        let series = match self.series.get(key) {
+            Some(series) => series,
+            None => return Vec::new(),
+        };
+
+        let overlaps = |block_start: u64, block_end: u64| block_start < end && block_end > start;
+
+        let mut blocks: Vec<Block> = series.sealed
+            .iter()
+            .filter(|block| overlaps(block.start, block.end))
+            .cloned()
+            .collect();
+        if let Some(ref head) = series.head {
+            if overlaps(head.start, head.end) {
+                blocks.push(head.snapshot());
+            }
+        }
+
+        blocks
+    }
+
+    /// query returns the `DataPoint`s of the series identified by `key` whose timestamps are
+    /// between `start`, inclusive, and `end`, exclusive
+    pub fn query(&self, key: &K, start: u64, end: u64) -> Result<Vec<DataPoint>, Error> {
+        let series = match self.series.get(key) {
+            Some(series) => series,
+            None => return Ok(Vec::new()),
+        };
+
+        // sealed blocks are decoded in place, only the head block has to be copied to seal it
+        let overlaps = |block_start: u64, block_end: u64| block_start < end && block_end > start;
+        let head = series.head
+            .as_ref()
+            .filter(|head| overlaps(head.start, head.end))
+            .map(HeadBlock::snapshot);
+        let sealed = series.sealed.iter().filter(|block| overlaps(block.start, block.end));
+
+        query_blocks(sealed.chain(head.as_ref()), start, end)
+    }
+}
+
+// query_blocks decodes the DataPoints in blocks whose timestamps are between start, inclusive, and
+// end, exclusive
+pub(crate) fn query_blocks<'a, I>(blocks: I, start: u64, end: u64) -> Result<Vec<DataPoint>, Error>
+    where I: IntoIterator<Item = &'a Block>
+{
+    let mut datapoints = Vec::new();
+    for block in blocks {
+        let decoded = block.datapoints()?;
+        datapoints.extend(decoded.into_iter().filter(|dp| dp.time >= start && dp.time < end));
+    }
+
+    Ok(datapoints)
+}
+
+impl<K> Default for Store<K>
+    where K: Hash + Eq
+{
+    fn default() -> Self {
+        Store::new()
+    }
+}
+
+pub mod sharded;
+pub mod block_file;
+pub mod wal;
+pub mod compact;
+pub mod reorder;
+pub mod retention;
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use super::{Store, Error, DEFAULT_BLOCK_DURATION};
+
+    #[test]
+    fn append_and_query() {
+        let mut store = Store::new();
+        let start = 1482892200 - 1482892200 % DEFAULT_BLOCK_DURATION;
+        let datapoints: Vec<DataPoint> = (0..1000)
+            .map(|i| DataPoint::new(start + i * 60, i as i64))
+            .collect();
+
+        for dp in &datapoints {
+            store.append("cpu", *dp).unwrap();
+            store.append("memory", DataPoint::new(dp.time, -dp.value)).unwrap();
+        }
+
+        assert_eq!(store.len(), 2);
+
+        // 1000 minutes cover eight full two hour blocks and part of a ninth
+        assert_eq!(store.sealed_blocks(&"cpu").len(), 8);
+        assert_eq!(store.sealed_blocks(&"cpu")[0].len(), 120);
+        assert_eq!(store.sealed_blocks(&"cpu")[1].start(), start + DEFAULT_BLOCK_DURATION);
+
+        assert_eq!(store.query(&"cpu", 0, u64::MAX).unwrap(), datapoints);
+        assert_eq!(store.query(&"cpu", start + 60 * 100, start + 60 * 900).unwrap(),
+                   &datapoints[100..900]);
+        assert_eq!(store.query(&"memory", start + 60 * 990, u64::MAX).unwrap(),
+                   vec![DataPoint::new(start + 60 * 990, -990),
+                        DataPoint::new(start + 60 * 991, -991),
+                        DataPoint::new(start + 60 * 992, -992),
+                        DataPoint::new(start + 60 * 993, -993),
+                        DataPoint::new(start + 60 * 994, -994),
+                        DataPoint::new(start + 60 * 995, -995),
+                        DataPoint::new(start + 60 * 996, -996),
+                        DataPoint::new(start + 60 * 997, -997),
+                        DataPoint::new(start + 60 * 998, -998),
+                        DataPoint::new(start + 60 * 999, -999)]);
+        assert_eq!(store.query(&"disk", 0, u64::MAX).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn append_out_of_order() {
+        let mut store = Store::with_block_duration(100);
+
+        store.append(1, DataPoint::new(150, 1)).unwrap();
+        store.append(1, DataPoint::new(160, 2)).unwrap();
+        assert_eq!(store.append(1, DataPoint::new(155, 3)), Err(Error::OutOfOrder));
+        assert_eq!(store.append(1, DataPoint::new(50, 3)), Err(Error::OutOfOrder));
+
+        // once the head block is sealed the sealed block's window is still closed to appends
+        store.seal(200);
+        assert_eq!(store.sealed_blocks(&1).len(), 1);
+        assert_eq!(store.append(1, DataPoint::new(155, 3)), Err(Error::OutOfOrder));
+        assert_eq!(store.append(1, DataPoint::new(170, 3)), Err(Error::Sealed));
+
+        store.append(1, DataPoint::new(230, 4)).unwrap();
+        assert_eq!(store.query(&1, 0, u64::MAX).unwrap(),
+                   vec![DataPoint::new(150, 1), DataPoint::new(160, 2), DataPoint::new(230, 4)]);
+    }
+}
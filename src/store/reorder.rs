@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::mem;
+
+use DataPoint;
+use store::{Error, Store};
+
+/// LatePolicy
+///
+/// LatePolicy determines what a `ReorderBuffer` does with a `DataPoint` which arrives after newer
+/// `DataPoint`s of its series have already been committed to the store.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LatePolicy {
+    /// Fail with `Error::TooLate`.
+    Reject,
+
+    /// Set the `DataPoint` aside, it can be retrieved with `take_late` and merged into the
+    /// series later, for example with `compact`.
+    Collect,
+}
+
+// Pending holds the DataPoints of a series which have not been committed yet
+#[derive(Debug, Default)]
+struct Pending {
+    datapoints: Vec<DataPoint>, // sorted by time
+    max_time: u64, // newest timestamp seen
+    committed: Option<u64>, // timestamp of the last DataPoint committed
+}
+
+/// ReorderBuffer
+///
+/// ReorderBuffer sits in front of a `Store` and accepts `DataPoint`s in any order within a
+/// window. `DataPoint`s are buffered and sorted, and are only committed to the store once they are
+/// more than `window` older than the newest `DataPoint` of their series. A `DataPoint` is late if
+/// it is older than a `DataPoint` which has already been committed, which can only happen if it
+/// arrives more than `window` after a newer `DataPoint` of its series.
+#[derive(Debug)]
+pub struct ReorderBuffer<K: Hash + Eq + Clone> {
+    store: Store<K>,
+    window: u64,
+    late_policy: LatePolicy,
+    pending: HashMap<K, Pending>,
+    late: Vec<(K, DataPoint)>,
+}
+
+impl<K> ReorderBuffer<K>
+    where K: Hash + Eq + Clone
+{
+    /// new creates a new ReorderBuffer which commits `DataPoint`s to `store` once they are
+    /// `window` older than the newest `DataPoint` of their series, late `DataPoint`s are rejected
+    pub fn new(store: Store<K>, window: u64) -> Self {
+        ReorderBuffer {
+            store,
+            window,
+            late_policy: LatePolicy::Reject,
+            pending: HashMap::new(),
+            late: Vec::new(),
+        }
+    }
+
+    /// late_policy sets what happens to `DataPoint`s which arrive too late to be committed
+    pub fn late_policy(mut self, late_policy: LatePolicy) -> Self {
+        self.late_policy = late_policy;
+        self
+    }
+
+    /// store returns the store `DataPoint`s are committed to
+    pub fn store(&self) -> &Store<K> {
+        &self.store
+    }
+
+    /// append adds `dp` to the series identified by `key`, committing any of the series'
+    /// buffered `DataPoint`s which have fallen out of the window
+    pub fn append(&mut self, key: K, dp: DataPoint) -> Result<(), Error> {
+        let pending = self.pending.entry(key.clone()).or_default();
+
+        if pending.committed.is_some_and(|committed| dp.time < committed) {
+            return match self.late_policy {
+                LatePolicy::Reject => Err(Error::TooLate),
+                LatePolicy::Collect => {
+                    self.late.push((key, dp));
+                    Ok(())
+                }
+            };
+        }
+
+        // insert after any DataPoints with the same timestamp so they keep their order
+        let i = pending.datapoints.partition_point(|pending| pending.time <= dp.time);
+        pending.datapoints.insert(i, dp);
+        pending.max_time = pending.max_time.max(dp.time);
+
+        let watermark = pending.max_time.saturating_sub(self.window);
+        let n = pending.datapoints.partition_point(|pending| pending.time < watermark);
+        commit(&mut self.store, &key, pending, n)
+    }
+
+    /// flush commits every buffered `DataPoint`, any `DataPoint` which arrives afterwards and is
+    /// older than the newest `DataPoint` of its series is late
+    pub fn flush(&mut self) -> Result<(), Error> {
+        for (key, pending) in &mut self.pending {
+            let n = pending.datapoints.len();
+            commit(&mut self.store, key, pending, n)?;
+        }
+
+        Ok(())
+    }
+
+    /// take_late returns the late `DataPoint`s collected since it was last called
+    pub fn take_late(&mut self) -> Vec<(K, DataPoint)> {
+        mem::take(&mut self.late)
+    }
+
+    /// query returns the `DataPoint`s of the series identified by `key` whose timestamps are
+    /// between `start`, inclusive, and `end`, exclusive, including those still buffered
+    pub fn query(&self, key: &K, start: u64, end: u64) -> Result<Vec<DataPoint>, Error> {
+        let mut datapoints = self.store.query(key, start, end)?;
+        if let Some(pending) = self.pending.get(key) {
+            datapoints.extend(pending.datapoints
+                .iter()
+                .filter(|dp| dp.time >= start && dp.time < end));
+        }
+
+        Ok(datapoints)
+    }
+
+    /// into_store flushes the buffer and returns the underlying store
+    pub fn into_store(mut self) -> Result<Store<K>, Error> {
+        self.flush()?;
+        Ok(self.store)
+    }
+}
+
+// commit appends the first n buffered DataPoints of a series to store
+fn commit<K>(store: &mut Store<K>, key: &K, pending: &mut Pending, n: usize) -> Result<(), Error>
+    where K: Hash + Eq + Clone
+{
+    let mut result = Ok(());
+    let mut committed = 0;
+    for dp in &pending.datapoints[..n] {
+        if let Err(err) = store.append(key.clone(), *dp) {
+            result = Err(err);
+            break;
+        }
+        pending.committed = Some(dp.time);
+        committed += 1;
+    }
+
+    // DataPoints which could not be committed stay buffered
+    pending.datapoints.drain(..committed);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use store::{Error, Store};
+    use super::{LatePolicy, ReorderBuffer};
+
+    #[test]
+    fn reorder_within_window() {
+        let mut buffer = ReorderBuffer::new(Store::new(), 30);
+
+        // every DataPoint arrives up to 20 seconds late
+        let times = [10, 30, 20, 0, 40, 60, 50, 70, 100, 90, 80, 110];
+        for time in &times {
+            buffer.append("cpu", DataPoint::new(*time, *time as i64)).unwrap();
+        }
+
+        // DataPoints more than 30 seconds older than the newest are committed to the store
+        assert_eq!(buffer.store().query(&"cpu", 0, u64::MAX).unwrap().len(), 8);
+
+        let expected: Vec<DataPoint> = (0..12)
+            .map(|i| DataPoint::new(i * 10, i as i64 * 10))
+            .collect();
+        assert_eq!(buffer.query(&"cpu", 0, u64::MAX).unwrap(), expected);
+
+        let store = buffer.into_store().unwrap();
+        assert_eq!(store.query(&"cpu", 0, u64::MAX).unwrap(), expected);
+    }
+
+    #[test]
+    fn late_datapoints() {
+        let mut buffer = ReorderBuffer::new(Store::new(), 30);
+        for time in &[0, 10, 20, 30, 40, 50] {
+            buffer.append(1, DataPoint::new(*time, 1)).unwrap();
+        }
+
+        // 10 has been committed so 5 is too late, but 15 can still be committed in order even
+        // though it is outside the window
+        assert_eq!(buffer.append(1, DataPoint::new(5, 2)), Err(Error::TooLate));
+        buffer.append(1, DataPoint::new(15, 2)).unwrap();
+
+        let mut buffer = buffer.late_policy(LatePolicy::Collect);
+        buffer.append(1, DataPoint::new(5, 3)).unwrap();
+        buffer.append(2, DataPoint::new(5, 3)).unwrap();
+        assert_eq!(buffer.take_late(), vec![(1, DataPoint::new(5, 3))]);
+        assert_eq!(buffer.take_late(), vec![]);
+
+        assert_eq!(buffer.query(&1, 0, u64::MAX).unwrap(),
+                   vec![DataPoint::new(0, 1),
+                        DataPoint::new(10, 1),
+                        DataPoint::new(15, 2),
+                        DataPoint::new(20, 1),
+                        DataPoint::new(30, 1),
+                        DataPoint::new(40, 1),
+                        DataPoint::new(50, 1)]);
+    }
+
+    #[test]
+    fn failed_commit_keeps_datapoints() {
+        let mut store = Store::new();
+        store.append("cpu", DataPoint::new(90, 1)).unwrap();
+
+        // committing 20 fails since the store already holds a newer DataPoint
+        let mut buffer = ReorderBuffer::new(store, 30);
+        buffer.append("cpu", DataPoint::new(20, 2)).unwrap();
+        assert_eq!(buffer.append("cpu", DataPoint::new(60, 3)), Err(Error::OutOfOrder));
+
+        assert_eq!(buffer.query(&"cpu", 0, u64::MAX).unwrap(),
+                   vec![DataPoint::new(90, 1), DataPoint::new(20, 2), DataPoint::new(60, 3)]);
+    }
+}
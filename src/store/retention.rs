@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use store::{Block, Store};
+
+/// Reason
+///
+/// Reason is the limit of a `Retention` which caused a block to be evicted.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Reason {
+    MaxAge,
+    MaxSeriesBytes,
+    MaxBytes,
+}
+
+/// Evicted
+///
+/// Evicted is a sealed block which has been removed from a `Store` by `Retention::apply`, along
+/// with the key of its series, so that it can be dropped or handed off to slower storage.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Evicted<K> {
+    pub key: K,
+    pub block: Block,
+    pub reason: Reason,
+}
+
+/// Retention
+///
+/// Retention is a set of limits on how long, and how many bytes of, sealed blocks are kept in a
+/// `Store`. Whole blocks are evicted, oldest first, and head blocks are never evicted, so a series
+/// can exceed its limits until its head block is sealed.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Retention {
+    max_age: Option<u64>,
+    max_series_bytes: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+impl Retention {
+    /// new creates a new Retention without any limits
+    pub fn new() -> Self {
+        Retention::default()
+    }
+
+    /// max_age evicts blocks once every `DataPoint` they could hold is more than `max_age`
+    /// older than the time passed to `apply`, that is once the end of their window is at least
+    /// `max_age` before that time
+    pub fn max_age(mut self, max_age: u64) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// max_series_bytes evicts the oldest blocks of a series while its sealed blocks take up
+    /// more than `max_series_bytes` bytes
+    pub fn max_series_bytes(mut self, max_series_bytes: usize) -> Self {
+        self.max_series_bytes = Some(max_series_bytes);
+        self
+    }
+
+    /// max_bytes evicts the oldest blocks across every series while the sealed blocks of the
+    /// store take up more than `max_bytes` bytes
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// apply evicts the sealed blocks of `store` which exceed the limits as of `now` and returns
+    /// them ordered by time. Series which are left without any blocks are removed from the store
+    pub fn apply<K>(&self, store: &mut Store<K>, now: u64) -> Vec<Evicted<K>>
+        where K: Hash + Eq + Clone
+    {
+        let mut evicted = Vec::new();
+
+        for (key, series) in &mut store.series {
+            if let Some(max_age) = self.max_age {
+                let cutoff = now.saturating_sub(max_age);
+                let n = series.sealed.partition_point(|block| block.end <= cutoff);
+                evict(&mut evicted, key, &mut series.sealed, n, Reason::MaxAge);
+            }
+
+            if let Some(max_series_bytes) = self.max_series_bytes {
+                let n = excess(series.sealed.iter(), max_series_bytes);
+                evict(&mut evicted, key, &mut series.sealed, n, Reason::MaxSeriesBytes);
+            }
+        }
+
+        if let Some(max_bytes) = self.max_bytes {
+            let mut blocks: Vec<(&Block, &K)> = store.series
+                .iter()
+                .flat_map(|(key, series)| series.sealed.iter().map(move |block| (block, key)))
+                .collect();
+            blocks.sort_by_key(|&(block, _)| block.start);
+
+            let n = excess(blocks.iter().map(|&(block, _)| block), max_bytes);
+            let mut counts: HashMap<K, usize> = HashMap::new();
+            for &(_, key) in &blocks[..n] {
+                *counts.entry(key.clone()).or_default() += 1;
+            }
+
+            for (key, n) in counts {
+                let series = store.series.get_mut(&key).unwrap();
+                evict(&mut evicted, &key, &mut series.sealed, n, Reason::MaxBytes);
+            }
+        }
+
+        store.series.retain(|_, series| series.head.is_some() || !series.sealed.is_empty());
+
+        evicted.sort_by_key(|evicted| evicted.block.start);
+        evicted
+    }
+}
+
+// excess returns how many of blocks, oldest first, need to be evicted for the rest to fit in
+// max_bytes
+fn excess<'a, I>(blocks: I, max_bytes: usize) -> usize
+    where I: DoubleEndedIterator<Item = &'a Block> + ExactSizeIterator
+{
+    let len = blocks.len();
+    let mut total = 0;
+    for (kept, block) in blocks.rev().enumerate() {
+        total += block.bytes().len();
+        if total > max_bytes {
+            return len - kept;
+        }
+    }
+
+    0
+}
+
+// evict moves the first n blocks of sealed to evicted
+fn evict<K: Clone>(evicted: &mut Vec<Evicted<K>>,
+                   key: &K,
+                   sealed: &mut Vec<Block>,
+                   n: usize,
+                   reason: Reason) {
+    evicted.extend(sealed.drain(..n).map(|block| {
+        Evicted {
+            key: key.clone(),
+            block,
+            reason,
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use DataPoint;
+    use store::Store;
+    use super::{Reason, Retention};
+
+    // store creates a store with blocks 100 long holding DataPoints every 10 seconds from 0 to
+    // 1000 for series 1 and from 500 to 990 for series 2
+    fn store() -> Store<u64> {
+        let mut store = Store::with_block_duration(100);
+        for time in (0..1000).step_by(10) {
+            store.append(1, DataPoint::new(time, 1)).unwrap();
+            if time >= 500 {
+                store.append(2, DataPoint::new(time, 2)).unwrap();
+            }
+        }
+        store.append(1, DataPoint::new(1000, 1)).unwrap();
+        store
+    }
+
+    #[test]
+    fn retention_max_age() {
+        let mut store = store();
+
+        let evicted = Retention::new().max_age(500).apply(&mut store, 1050);
+        let starts: Vec<(u64, u64)> = evicted.iter().map(|e| (e.key, e.block.start())).collect();
+        assert_eq!(starts, vec![(1, 0), (1, 100), (1, 200), (1, 300), (1, 400)]);
+        assert!(evicted.iter().all(|e| e.reason == Reason::MaxAge));
+        assert_eq!(evicted[0].block.datapoints().unwrap()[0], DataPoint::new(0, 1));
+
+        assert_eq!(store.query(&1, 0, 600).unwrap()[0], DataPoint::new(500, 1));
+        assert_eq!(store.query(&2, 0, u64::MAX).unwrap().len(), 50);
+
+        // the head blocks are kept even once they are old
+        let evicted = Retention::new().max_age(0).apply(&mut store, u64::MAX);
+        assert_eq!(evicted.len(), 9);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.query(&1, 0, u64::MAX).unwrap(), vec![DataPoint::new(1000, 1)]);
+    }
+
+    #[test]
+    fn retention_max_bytes() {
+        let mut store = store();
+        let block_len = store.sealed_blocks(&1)[0].bytes().len();
+        assert!(store.sealed_blocks(&1).iter().all(|block| block.bytes().len() == block_len));
+
+        // series 1 keeps its newest four blocks, series 2 only has four
+        let evicted = Retention::new()
+            .max_series_bytes(block_len * 4)
+            .apply(&mut store, 0);
+        assert_eq!(evicted.len(), 6);
+        assert!(evicted.iter().all(|e| e.key == 1 && e.reason == Reason::MaxSeriesBytes));
+        assert_eq!(store.sealed_blocks(&1)[0].start(), 600);
+        assert_eq!(store.sealed_blocks(&2).len(), 4);
+
+        // across both series only the newest three blocks are kept
+        let evicted = Retention::new()
+            .max_bytes(block_len * 3)
+            .apply(&mut store, 0);
+        let starts: Vec<(u64, u64)> = evicted.iter().map(|e| (e.key, e.block.start())).collect();
+        assert_eq!(starts.len(), 5);
+        assert!(starts.contains(&(1, 600)));
+        assert!(starts.contains(&(1, 700)));
+        assert!(starts.contains(&(2, 500)));
+        assert!(starts.contains(&(2, 600)));
+        assert!(starts.contains(&(2, 700)));
+        assert_eq!(store.sealed_blocks(&1).len(), 2);
+        assert_eq!(store.sealed_blocks(&2).len(), 1);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+
+use DataPoint;
+use store::{query_blocks, Block, Error, Store, DEFAULT_BLOCK_DURATION};
+
+/// ShardedStore
+///
+/// ShardedStore is a thread-safe `Store` which spreads its series across a number of shards,
+/// each protected by its own lock, so that threads appending to series in different shards do
+/// not contend with each other. Queries copy the blocks they need while holding a shard's lock,
+/// which gives a consistent snapshot of the head block, and decode them after releasing it.
+#[derive(Debug)]
+pub struct ShardedStore<K: Hash + Eq> {
+    shards: Vec<RwLock<Store<K>>>,
+}
+
+impl<K> ShardedStore<K>
+    where K: Hash + Eq
+{
+    /// new creates a new ShardedStore with `shards` shards whose blocks are
+    /// DEFAULT_BLOCK_DURATION long
+    pub fn new(shards: usize) -> Self {
+        ShardedStore::with_block_duration(shards, DEFAULT_BLOCK_DURATION)
+    }
+
+    /// with_block_duration creates a new ShardedStore with `shards` shards whose blocks are
+    /// `block_duration` long
+    pub fn with_block_duration(shards: usize, block_duration: u64) -> Self {
+        assert!(shards > 0, "a ShardedStore needs at least one shard");
+
+        ShardedStore {
+            shards: (0..shards)
+                .map(|_| RwLock::new(Store::with_block_duration(block_duration)))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &RwLock<Store<K>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+
+    /// len returns the number of series in the store
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().unwrap().len()).sum()
+    }
+
+    /// is_empty returns true if the store contains no series
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// append adds `dp` to the series identified by `key`, see `Store::append`
+    pub fn append(&self, key: K, dp: DataPoint) -> Result<(), Error> {
+        self.shard(&key).write().unwrap().append(key, dp)
+    }
+
+    /// seal seals every head block whose window ends at or before `time`, see `Store::seal`
+    pub fn seal(&self, time: u64) {
+        for shard in &self.shards {
+            shard.write().unwrap().seal(time);
+        }
+    }
+
+    /// blocks returns copies of the blocks of the series identified by `key` which overlap the
+    /// window between `start`, inclusive, and `end`, exclusive, see `Store::blocks`
+    pub fn blocks(&self, key: &K, start: u64, end: u64) -> Vec<Block> {
+        self.shard(key).read().unwrap().blocks(key, start, end)
+    }
+
+    /// query returns the `DataPoint`s of the series identified by `key` whose timestamps are
+    /// between `start`, inclusive, and `end`, exclusive. The blocks are decoded after the shard's
+    /// lock has been released
+    pub fn query(&self, key: &K, start: u64, end: u64) -> Result<Vec<DataPoint>, Error> {
+        let blocks = self.blocks(key, start, end);
+        query_blocks(&blocks, start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use DataPoint;
+    use super::ShardedStore;
+
+    const THREADS: u64 = 8;
+    const SERIES_PER_THREAD: u64 = 25;
+    const POINTS: u64 = 400;
+
+    fn datapoint(series: u64, i: u64) -> DataPoint {
+        DataPoint::new(1482892200 + i * 30, (series * POINTS + i) as i64)
+    }
+
+    #[test]
+    fn concurrent_appends() {
+        let store = Arc::new(ShardedStore::new(16));
+
+        let writers: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let store = store.clone();
+                thread::spawn(move || for i in 0..POINTS {
+                    for s in 0..SERIES_PER_THREAD {
+                        let series = t * SERIES_PER_THREAD + s;
+                        store.append(series, datapoint(series, i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(store.len(), (THREADS * SERIES_PER_THREAD) as usize);
+        for series in 0..THREADS * SERIES_PER_THREAD {
+            let expected: Vec<DataPoint> = (0..POINTS).map(|i| datapoint(series, i)).collect();
+            assert_eq!(store.query(&series, 0, u64::MAX).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn concurrent_appends_and_queries() {
+        let store = Arc::new(ShardedStore::new(4));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let writers: Vec<_> = (0..THREADS)
+            .map(|series| {
+                let store = store.clone();
+                thread::spawn(move || for i in 0..POINTS {
+                    store.append(series, datapoint(series, i)).unwrap();
+                })
+            })
+            .collect();
+
+        // every query must see a prefix of the series, never a partially appended DataPoint
+        let readers: Vec<_> = (0..THREADS)
+            .map(|series| {
+                let store = store.clone();
+                let done = done.clone();
+                thread::spawn(move || while !done.load(Ordering::SeqCst) {
+                    let datapoints = store.query(&series, 0, u64::MAX).unwrap();
+                    for (i, dp) in datapoints.iter().enumerate() {
+                        assert_eq!(*dp, datapoint(series, i as u64));
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        store.seal(u64::MAX);
+        for series in 0..THREADS {
+            let expected: Vec<DataPoint> = (0..POINTS).map(|i| datapoint(series, i)).collect();
+            assert_eq!(store.query(&series, 0, u64::MAX).unwrap(), expected);
+        }
+    }
+}
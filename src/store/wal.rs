@@ -0,0 +1,375 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use DataPoint;
+use checksum;
+use store::{Error, Store};
+
+// A WAL is a directory of segment files named by their sequence number. Every segment starts with
+// MAGIC and is followed by records of RECORD_LEN bytes, each holding the series ID (u64), the time
+// (u64) and the value (i64) of a DataPoint followed by a CRC32C of those 24 bytes. All integers
+// are big endian.
+
+/// MAGIC starts every WAL segment
+pub const MAGIC: [u8; 4] = *b"TSZW";
+
+/// RECORD_LEN is the length, in bytes, of a record
+pub const RECORD_LEN: usize = 28;
+
+/// DEFAULT_SEGMENT_SIZE is the default size, in bytes, at which a new segment is started
+pub const DEFAULT_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+const SEGMENT_EXTENSION: &str = "wal";
+
+/// Replay
+///
+/// Replay counts the records replayed into a `Store` by `Wal::replay_into`.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Replay {
+    /// The number of `DataPoint`s appended to the store.
+    pub appended: usize,
+
+    /// The number of `DataPoint`s the store rejected as out of order or as falling within a
+    /// block which had already been sealed.
+    pub skipped: usize,
+}
+
+// Segment is a segment file along with the newest timestamp written to it
+#[derive(Debug)]
+struct Segment {
+    id: u64,
+    max_time: Option<u64>,
+}
+
+/// Wal
+///
+/// Wal is a write-ahead log of the `DataPoint`s appended to a `Store`, so that the head blocks
+/// which are only held in memory can be rebuilt after a crash. Appends are buffered until `sync`
+/// is called. Once the blocks holding every `DataPoint` older than some time have been sealed and
+/// persisted, `truncate` removes the segments which only hold those `DataPoint`s.
+#[derive(Debug)]
+pub struct Wal {
+    dir: PathBuf,
+    segment_size: u64,
+    segments: Vec<Segment>, // ordered by id, the last segment is the one being written
+    w: BufWriter<File>,
+    len: u64, // number of bytes written to the current segment
+}
+
+impl Wal {
+    /// open opens the WAL in the directory `dir`, creating it if it does not exist, with
+    /// segments of DEFAULT_SEGMENT_SIZE
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        Wal::with_segment_size(dir, DEFAULT_SEGMENT_SIZE)
+    }
+
+    /// with_segment_size opens the WAL in the directory `dir`, creating it if it does not exist,
+    /// and starts a new segment whenever the current one would grow beyond `segment_size` bytes.
+    /// Existing segments are never appended to, a new segment is always started
+    pub fn with_segment_size<P: AsRef<Path>>(dir: P, segment_size: u64) -> io::Result<Self> {
+        assert!(segment_size >= (MAGIC.len() + RECORD_LEN) as u64,
+                "segments must be large enough to hold at least one record");
+
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        for id in list_segments(&dir)? {
+            let max_time = read_segment(&segment_path(&dir, id))?
+                .iter()
+                .map(|&(_, dp)| dp.time)
+                .max();
+            segments.push(Segment { id, max_time });
+        }
+
+        let id = segments.last().map_or(0, |segment| segment.id + 1);
+        let w = create_segment(&dir, id)?;
+        segments.push(Segment { id, max_time: None });
+
+        Ok(Wal {
+            dir,
+            segment_size,
+            segments,
+            w,
+            len: MAGIC.len() as u64,
+        })
+    }
+
+    /// append adds a record of `dp` being appended to the series `series`
+    pub fn append(&mut self, series: u64, dp: DataPoint) -> io::Result<()> {
+        if self.len + RECORD_LEN as u64 > self.segment_size {
+            self.roll()?;
+        }
+
+        let mut record = [0; RECORD_LEN];
+        record[0..8].copy_from_slice(&series.to_be_bytes());
+        record[8..16].copy_from_slice(&dp.time.to_be_bytes());
+        record[16..24].copy_from_slice(&dp.value.to_be_bytes());
+        let crc = checksum::crc32c(&record[..24]);
+        record[24..28].copy_from_slice(&crc.to_be_bytes());
+
+        self.w.write_all(&record)?;
+        self.len += RECORD_LEN as u64;
+
+        let segment = self.segments.last_mut().unwrap();
+        segment.max_time = Some(segment.max_time.map_or(dp.time, |time| time.max(dp.time)));
+
+        Ok(())
+    }
+
+    /// sync flushes buffered records and waits for them to reach the disk
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.w.flush()?;
+        self.w.get_ref().sync_data()
+    }
+
+    /// replay returns every record in the WAL in the order they were appended. A segment is read
+    /// up to its first incomplete or damaged record, which is what a crash part way through a
+    /// write leaves behind
+    pub fn replay(&mut self) -> io::Result<Vec<(u64, DataPoint)>> {
+        self.w.flush()?;
+
+        let mut records = Vec::new();
+        for segment in &self.segments {
+            records.extend(read_segment(&segment_path(&self.dir, segment.id))?);
+        }
+
+        Ok(records)
+    }
+
+    /// replay_into appends every record in the WAL to `store`, returning how many `DataPoint`s
+    /// were appended and how many were skipped because the store rejected them as out of order
+    /// or sealed, which happens when `store` already holds some of the records
+    pub fn replay_into(&mut self, store: &mut Store<u64>) -> io::Result<Replay> {
+        let mut replay = Replay::default();
+        for (series, dp) in self.replay()? {
+            match store.append(series, dp) {
+                Ok(()) => replay.appended += 1,
+                Err(Error::OutOfOrder) | Err(Error::Sealed) => replay.skipped += 1,
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            }
+        }
+
+        Ok(replay)
+    }
+
+    /// truncate removes every segment which only holds `DataPoint`s older than `before`, it
+    /// should be called once the blocks holding those `DataPoint`s have been sealed and persisted
+    pub fn truncate(&mut self, before: u64) -> io::Result<()> {
+        let is_old = |segment: &Segment| segment.max_time.is_none_or(|time| time < before);
+
+        // the current segment can only be removed once a new one has been started
+        let current = self.segments.last().unwrap();
+        if current.max_time.is_some() && is_old(current) {
+            self.roll()?;
+        }
+
+        let current = self.segments.pop().unwrap();
+        let mut kept = Vec::new();
+        let mut removed = false;
+        for segment in self.segments.drain(..) {
+            if is_old(&segment) {
+                fs::remove_file(segment_path(&self.dir, segment.id))?;
+                removed = true;
+            } else {
+                kept.push(segment);
+            }
+        }
+        kept.push(current);
+        self.segments = kept;
+
+        if removed {
+            sync_dir(&self.dir)?;
+        }
+
+        Ok(())
+    }
+
+    // roll finishes the current segment and starts a new one
+    fn roll(&mut self) -> io::Result<()> {
+        self.sync()?;
+
+        let id = self.segments.last().unwrap().id + 1;
+        self.w = create_segment(&self.dir, id)?;
+        self.segments.push(Segment { id, max_time: None });
+        self.len = MAGIC.len() as u64;
+
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", id, SEGMENT_EXTENSION))
+}
+
+// list_segments returns the ids of the segments in dir in ascending order
+fn list_segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()?.parse().ok()) {
+            ids.push(id);
+        }
+    }
+
+    ids.sort();
+    Ok(ids)
+}
+
+// create_segment creates the segment id in dir and syncs dir so the new segment survives a crash
+fn create_segment(dir: &Path, id: u64) -> io::Result<BufWriter<File>> {
+    let mut w = BufWriter::new(File::create(segment_path(dir, id))?);
+    w.write_all(&MAGIC)?;
+    sync_dir(dir)?;
+    Ok(w)
+}
+
+// sync_dir waits for the entries of dir to reach the disk, so that segments which were created
+// or removed stay that way after a crash. Directories can only be opened and synced on unix
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// read_segment reads the records of a segment up to its first incomplete or damaged record
+fn read_segment(path: &Path) -> io::Result<Vec<(u64, DataPoint)>> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("{} is not a WAL segment", path.display())));
+    }
+
+    let mut records = Vec::new();
+    for record in bytes[MAGIC.len()..].chunks_exact(RECORD_LEN) {
+        if checksum::crc32c(&record[..24]).to_be_bytes() != record[24..28] {
+            break;
+        }
+
+        let mut buf = [0; 8];
+        buf.copy_from_slice(&record[0..8]);
+        let series = u64::from_be_bytes(buf);
+        buf.copy_from_slice(&record[8..16]);
+        let time = u64::from_be_bytes(buf);
+        buf.copy_from_slice(&record[16..24]);
+        let value = i64::from_be_bytes(buf);
+
+        records.push((series, DataPoint::new(time, value)));
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::path::PathBuf;
+    use std::process;
+
+    use DataPoint;
+    use store::Store;
+    use super::{Replay, Wal, MAGIC, RECORD_LEN, list_segments, segment_path};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tsz-wal-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn datapoint(i: u64) -> DataPoint {
+        DataPoint::new(1482892200 + i * 10, i as i64 - 50)
+    }
+
+    #[test]
+    fn replay_after_reopening() {
+        let dir = temp_dir("replay");
+
+        let mut wal = Wal::open(&dir).unwrap();
+        for i in 0..100 {
+            wal.append(i % 3, datapoint(i)).unwrap();
+        }
+        wal.sync().unwrap();
+        drop(wal);
+
+        let mut wal = Wal::open(&dir).unwrap();
+        let mut store = Store::new();
+        assert_eq!(wal.replay_into(&mut store).unwrap(), Replay { appended: 100, skipped: 0 });
+
+        for series in 0..3 {
+            let expected: Vec<DataPoint> = (0..100)
+                .filter(|i| i % 3 == series)
+                .map(datapoint)
+                .collect();
+            assert_eq!(store.query(&series, 0, u64::MAX).unwrap(), expected);
+        }
+
+        // replaying into a store which already holds the records appends none of them again
+        store.seal(u64::MAX);
+        assert_eq!(wal.replay_into(&mut store).unwrap(), Replay { appended: 0, skipped: 100 });
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_torn_write() {
+        let dir = temp_dir("torn");
+
+        let mut wal = Wal::open(&dir).unwrap();
+        for i in 0..10 {
+            wal.append(1, datapoint(i)).unwrap();
+        }
+        wal.sync().unwrap();
+        drop(wal);
+
+        // cut the last record in half as if the process crashed while writing it
+        let path = segment_path(&dir, 0);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len((MAGIC.len() + RECORD_LEN * 9 + RECORD_LEN / 2) as u64).unwrap();
+
+        let mut wal = Wal::open(&dir).unwrap();
+        let expected: Vec<(u64, DataPoint)> = (0..9).map(|i| (1, datapoint(i))).collect();
+        assert_eq!(wal.replay().unwrap(), expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn truncate_persisted_segments() {
+        let dir = temp_dir("truncate");
+
+        // each segment holds ten records
+        let mut wal = Wal::with_segment_size(&dir, (MAGIC.len() + RECORD_LEN * 10) as u64)
+            .unwrap();
+        for i in 0..35 {
+            wal.append(1, datapoint(i)).unwrap();
+        }
+        assert_eq!(list_segments(&dir).unwrap(), vec![0, 1, 2, 3]);
+
+        // the segment holding DataPoints 20 to 29 is still needed
+        wal.truncate(datapoint(25).time).unwrap();
+        assert_eq!(list_segments(&dir).unwrap(), vec![2, 3]);
+        let expected: Vec<(u64, DataPoint)> = (20..35).map(|i| (1, datapoint(i))).collect();
+        assert_eq!(wal.replay().unwrap(), expected);
+
+        // once everything is persisted only a new, empty, segment is left
+        wal.truncate(u64::MAX).unwrap();
+        assert_eq!(list_segments(&dir).unwrap(), vec![4]);
+        assert_eq!(wal.replay().unwrap(), vec![]);
+
+        wal.append(1, datapoint(35)).unwrap();
+        assert_eq!(wal.replay().unwrap(), vec![(1, datapoint(35))]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
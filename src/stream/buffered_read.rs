@@ -23,6 +23,11 @@ impl BufferedReader {
         }
     }
 
+    /// bytes returns the whole buffer, including the bytes which have already been read
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
     fn get_byte(&mut self) -> Result<u8, Error> {
         self.bytes.get(self.index).map(|byte| *byte).ok_or(Error::EOF)
     }
@@ -56,9 +61,14 @@ impl Read for BufferedReader {
             return self.get_byte();
         }
 
+        // check the next byte exists before moving on to it so a failed read leaves the position
+        // at the end of the stream
+        let next = self.bytes.get(self.index + 1).copied().ok_or(Error::EOF);
+
         if self.pos == 8 {
+            let b = next?;
             self.index += 1;
-            return self.get_byte();
+            return Ok(b);
         }
 
         let mut byte = 0;
@@ -66,8 +76,8 @@ impl Read for BufferedReader {
 
         byte = byte | (b.wrapping_shl(self.pos));
 
+        b = next?;
         self.index += 1;
-        b = self.get_byte()?;
 
         byte = byte | (b.wrapping_shr(8 - self.pos));
 
@@ -101,12 +111,16 @@ impl Read for BufferedReader {
         let index = self.index;
         let pos = self.pos;
 
-        let bits = self.read_bits(num)?;
+        let bits = self.read_bits(num);
 
         self.index = index;
         self.pos = pos;
 
-        Ok(bits)
+        bits
+    }
+
+    fn bits_read(&self) -> u64 {
+        self.index as u64 * 8 + self.pos as u64
     }
 }
 
@@ -210,4 +224,24 @@ mod tests {
 
         assert_eq!(b.peak_bits(22).err().unwrap(), Error::EOF);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bits_read() {
+        let bytes = vec![0b01010111, 0b00011101, 0b11110101, 0b00010100];
+        let mut b = BufferedReader::new(bytes.into_boxed_slice());
+
+        assert_eq!(b.bits_read(), 0);
+        b.read_bits(3).unwrap();
+        assert_eq!(b.bits_read(), 3);
+        b.peak_bits(8).unwrap();
+        assert_eq!(b.bits_read(), 3);
+        b.read_byte().unwrap();
+        assert_eq!(b.bits_read(), 11);
+        b.read_bits(5).unwrap();
+        assert_eq!(b.bits_read(), 16);
+        b.read_byte().unwrap();
+        assert_eq!(b.bits_read(), 24);
+        b.read_bit().unwrap();
+        assert_eq!(b.bits_read(), 25);
+    }
+}
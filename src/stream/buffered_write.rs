@@ -6,7 +6,7 @@ use stream::Write;
 /// BufferedWriter
 ///
 /// BufferedWriter writes bytes to a buffer.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BufferedWriter {
     buf: Vec<u8>,
     pos: u32, // position in the last byte in the buffer
@@ -105,6 +105,11 @@ impl Write for BufferedWriter {
         }
     }
 
+    fn bits_written(&self) -> u64 {
+        // pos is 8 when the buffer is empty, so this can never underflow
+        self.buf.len() as u64 * 8 + self.pos as u64 - 8
+    }
+
     fn close(self) -> Box<[u8]> {
         return self.buf.into_boxed_slice();
     }
@@ -251,4 +256,25 @@ mod tests {
         assert_eq!(b.buf[2], 156); // 0b10011100 = 156
         assert_eq!(b.buf[3], 207); // 0b11001111 = 207
     }
+
+    #[test]
+    fn bits_written() {
+        let mut b = BufferedWriter::new();
+        assert_eq!(b.bits_written(), 0);
+
+        b.write_bit(Bit::One);
+        assert_eq!(b.bits_written(), 1);
+
+        b.write_byte(9);
+        assert_eq!(b.bits_written(), 9);
+
+        b.write_bits(2508, 12);
+        assert_eq!(b.bits_written(), 21);
+
+        b.write_bits(3, 3);
+        assert_eq!(b.bits_written(), 24);
+
+        b.write_byte(9);
+        assert_eq!(b.bits_written(), 32);
+    }
 }
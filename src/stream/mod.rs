@@ -41,6 +41,9 @@ pub trait Read {
 
     /// Get the next `num` bits, but do not update place in stream.
     fn peak_bits(&mut self, num: u32) -> Result<u64, Error>;
+
+    /// Get the number of bits read from the underlying stream so far.
+    fn bits_read(&self) -> u64;
 }
 
 /// Write
@@ -56,6 +59,9 @@ pub trait Write {
     // Write the bottom `num` bits of `bits` to the underlying stream.
     fn write_bits(&mut self, bits: u64, num: u32);
 
+    // Get the number of bits written to the underlying stream so far.
+    fn bits_written(&self) -> u64;
+
     // Close the underlying stream and return a pointer to the array of bytes.
     fn close(self) -> Box<[u8]>;
 }
@@ -64,4 +70,6 @@ pub mod buffered_write;
 pub use self::buffered_write::BufferedWriter;
 
 pub mod buffered_read;
-pub use self::buffered_read::BufferedReader;
\ No newline at end of file
+pub use self::buffered_read::BufferedReader;
+pub mod slice_read;
+pub use self::slice_read::SliceReader;
@@ -0,0 +1,116 @@
+use std::cmp;
+
+use Bit;
+use stream::{Error, Read};
+
+/// SliceReader
+///
+/// SliceReader reads from a borrowed slice of bytes, such as a region of a memory mapped file,
+/// without copying them. Unlike `BufferedReader`, a read which fails leaves the position in the
+/// stream unchanged.
+#[derive(Debug, Clone)]
+pub struct SliceReader<'a> {
+    bytes: &'a [u8],
+    pos: u64, // position in bits from the start of bytes
+}
+
+impl<'a> SliceReader<'a> {
+    /// new creates a new `SliceReader` from `bytes`
+    pub fn new(bytes: &'a [u8]) -> Self {
+        SliceReader { bytes, pos: 0 }
+    }
+
+    fn remaining(&self) -> u64 {
+        self.bytes.len() as u64 * 8 - self.pos
+    }
+}
+
+impl<'a> Read for SliceReader<'a> {
+    fn read_bit(&mut self) -> Result<Bit, Error> {
+        self.read_bits(1).map(|bit| if bit == 0 { Bit::Zero } else { Bit::One })
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        self.read_bits(8).map(|byte| byte as u8)
+    }
+
+    fn read_bits(&mut self, mut num: u32) -> Result<u64, Error> {
+        // can't read more than 64 bits into a u64
+        if num > 64 {
+            num = 64;
+        }
+
+        if (num as u64) > self.remaining() {
+            return Err(Error::EOF);
+        }
+
+        let mut bits: u64 = 0;
+        while num > 0 {
+            // read as many bits as we need from the current byte
+            let byte = self.bytes[(self.pos / 8) as usize] as u64;
+            let available = 8 - (self.pos % 8) as u32;
+            let n = cmp::min(available, num);
+
+            let chunk = (byte >> (available - n)) & ((1 << n) - 1);
+            bits = bits.wrapping_shl(n) | chunk;
+
+            self.pos += n as u64;
+            num -= n;
+        }
+
+        Ok(bits)
+    }
+
+    fn peak_bits(&mut self, num: u32) -> Result<u64, Error> {
+        let pos = self.pos;
+        let bits = self.read_bits(num);
+        self.pos = pos;
+
+        bits
+    }
+
+    fn bits_read(&self) -> u64 {
+        self.pos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use Bit;
+    use stream::{Error, Read};
+    use super::SliceReader;
+
+    #[test]
+    fn read_mixed() {
+        let bytes = [0b01101101, 0b01101101];
+        let mut b = SliceReader::new(&bytes);
+
+        assert_eq!(b.read_bit().unwrap(), Bit::Zero);
+        assert_eq!(b.read_bits(3).unwrap(), 0b110);
+        assert_eq!(b.read_byte().unwrap(), 0b11010110);
+        assert_eq!(b.peak_bits(2).unwrap(), 0b11);
+        assert_eq!(b.read_bits(2).unwrap(), 0b11);
+        assert_eq!(b.read_bit().unwrap(), Bit::Zero);
+        assert_eq!(b.bits_read(), 15);
+
+        // a failed read does not move the position
+        assert_eq!(b.read_bits(2).err().unwrap(), Error::EOF);
+        assert_eq!(b.bits_read(), 15);
+        assert_eq!(b.read_bits(1).unwrap(), 0b1);
+        assert_eq!(b.read_bit().err().unwrap(), Error::EOF);
+    }
+
+    #[test]
+    fn read_bits() {
+        let bytes = [0b01010111, 0b00011101, 0b11110101, 0b00010100, 255, 255, 255, 255, 255];
+        let mut b = SliceReader::new(&bytes);
+
+        assert_eq!(b.read_bits(3).unwrap(), 0b010);
+        assert_eq!(b.read_bits(1).unwrap(), 0b1);
+        assert_eq!(b.read_bits(20).unwrap(), 0b01110001110111110101);
+        assert_eq!(b.read_bits(8).unwrap(), 0b00010100);
+        assert_eq!(b.read_bits(4).unwrap(), 0b1111);
+        assert_eq!(b.read_bits(36).unwrap(), (1 << 36) - 1);
+        assert_eq!(b.read_bits(1).err().unwrap(), Error::EOF);
+    }
+}
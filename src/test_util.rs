@@ -0,0 +1,32 @@
+use {DataPoint, Encode, StdDecoder, StdEncoder, SimplePredictor};
+use decode;
+use stream::{BufferedReader, BufferedWriter};
+
+// encode encodes datapoints with a StdEncoder which starts at the first DataPoint
+pub fn encode(datapoints: &[DataPoint]) -> Box<[u8]> {
+    let start = datapoints.first().map_or(0, |dp| dp.time);
+    let mut encoder = StdEncoder::new(start, BufferedWriter::new(), SimplePredictor::new());
+    for dp in datapoints {
+        encoder.encode(*dp);
+    }
+    encoder.close()
+}
+
+// decoder returns a StdDecoder of datapoints
+pub fn decoder(datapoints: &[DataPoint]) -> StdDecoder<BufferedReader, SimplePredictor> {
+    StdDecoder::new(BufferedReader::new(encode(datapoints)), SimplePredictor::new())
+}
+
+// decode decodes every DataPoint in bytes written by StdEncoder, panicking on any error
+pub fn decode(bytes: Box<[u8]>) -> Vec<DataPoint> {
+    let mut decoder = StdDecoder::new(BufferedReader::new(bytes), SimplePredictor::new());
+    decode::decode_all(&mut decoder).expect("Received an error from decoder")
+}
+
+// xorshift is a small deterministic generator of pseudo-random numbers for property tests
+pub fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}